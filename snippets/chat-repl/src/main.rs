@@ -0,0 +1,112 @@
+//! This example ties together the memory and model routing examples into a single chat app.
+//! Each message is routed to a specialized agent using the semantic router, and the conversation
+//! history is kept in a `ConversationMemory` so it carries over between agents.
+//! Once the conversation grows too long, it is compacted into a summary.
+//!
+//! Try asking a Rust question, then a follow-up maths question that refers back to it - for example:
+//! "How do I get the length of a Vec in Rust?" followed by "If it has 12 items, what is 15% of that?"
+use std::collections::HashMap;
+use std::io::Write;
+
+use common::models::openai::{AGENT_MODEL, EMBED_MODEL, SMALL_CHAT_MODEL};
+use memory::{ConversationMemory, ConversationStore};
+use model_routing::{create_semantic_router, semantic_route_query};
+use rig::{
+    agent::Agent,
+    client::{CompletionClient, EmbeddingsClient, ProviderClient},
+    completion::{Chat, Message},
+    providers::openai::{self, responses_api::ResponsesCompletionModel},
+};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = openai::Client::from_env();
+
+    let embedding_model = openai_client.embedding_model(EMBED_MODEL);
+    let router = create_semantic_router(&embedding_model).await?;
+
+    // One agent per route in the semantic router
+    let agents: HashMap<&str, Agent<ResponsesCompletionModel>> = HashMap::from([
+        (
+            "rust",
+            create_agent(
+                &openai_client,
+                "You are an expert coding assistant specializing in Rust programming.",
+            ),
+        ),
+        (
+            "coding",
+            create_agent(&openai_client, "You are a helpful programming assistant."),
+        ),
+        (
+            "math",
+            create_agent(
+                &openai_client,
+                "You are a mathematics expert who excels at solving complex problems.",
+            ),
+        ),
+    ]);
+
+    // A cheaper model is used to summarize the conversation when it gets too long
+    let summary_model = openai_client.completion_model(SMALL_CHAT_MODEL);
+    let mut memory = ConversationMemory::with_max_messages(10).with_auto_compact(true);
+
+    println!("Type a message and press enter to chat, or type \"exit\" to quit.");
+
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        input.clear();
+        if stdin.read_line(&mut input)? == 0 {
+            break;
+        }
+
+        let prompt = input.trim();
+        if prompt.is_empty() {
+            continue;
+        }
+        if prompt == "exit" {
+            break;
+        }
+
+        let route_name = semantic_route_query(prompt, &router, &embedding_model).await?;
+        let Some(agent) = agents.get(route_name.as_str()) else {
+            println!("No agent for route {route_name}, please try rephrasing your message.");
+            continue;
+        };
+        println!("[Routed to: {route_name}]");
+
+        // Once the conversation has been compacted, the summary takes the place of the older messages
+        let mut history = Vec::new();
+        if let Some(summary) = memory.summary() {
+            history.push(Message::user(format!(
+                "Previous conversation summary:\n{summary}"
+            )));
+        }
+        history.extend_from_slice(memory.get_messages());
+
+        let response = agent.chat(prompt, history).await?;
+        println!("{response}");
+
+        // The whole turn is recorded before compacting, so a question is never summarized
+        // separately from its answer
+        memory
+            .push_turn(
+                Message::user(prompt),
+                Message::assistant(&response),
+                &summary_model,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Creates an agent with the given preamble.
+fn create_agent(client: &openai::Client, preamble: &str) -> Agent<ResponsesCompletionModel> {
+    client.agent(AGENT_MODEL).preamble(preamble).build()
+}
@@ -0,0 +1,237 @@
+//! A circuit breaker for provider calls. After too many failures in a row, the breaker "opens"
+//! and calls fail straight away (rather than hammering a provider that's already struggling)
+//! until a cooldown has passed. The next call is then let through as a trial: if it succeeds
+//! the breaker closes again, and if it fails the breaker re-opens for another cooldown.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The state of a `CircuitBreaker`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through as normal.
+    Closed,
+    /// Too many calls have failed, so calls fail fast until the cooldown has passed.
+    Open,
+    /// The cooldown has passed, so the next call is let through to see if the provider has recovered.
+    /// Any other calls made while that trial call is in flight fail fast.
+    HalfOpen,
+}
+
+/// Why a call made through a `CircuitBreaker` failed.
+#[derive(Debug, thiserror::Error)]
+pub enum CircuitBreakerError<E> {
+    /// The breaker is open, so the call wasn't made. Contains how long is left of the cooldown
+    /// (which is zero if the cooldown has passed, but another caller's trial call is in flight).
+    #[error("Circuit breaker is open, try again in {0:?}")]
+    Open(Duration),
+    /// The call took longer than the breaker's per-call timeout.
+    #[error("Call timed out after {0:?}")]
+    Timeout(Duration),
+    /// The call was made, but failed.
+    #[error(transparent)]
+    Inner(E),
+}
+
+/// Counts consecutive failed calls, and fails fast once `failure_threshold` is reached.
+/// Every call is also given a timeout, which counts as a failure if it's hit.
+///
+/// The breaker can be shared between tasks (for example, in an `Arc`), so that they all stop
+/// calling the provider at once.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    call_timeout: Duration,
+    inner: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    /// When the breaker last opened, if it's open (or half-open)
+    opened_at: Option<Instant>,
+    /// Whether a trial call is being made while half-open
+    trial_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration, call_timeout: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            call_timeout,
+            inner: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// The breaker's current state. An open breaker becomes half-open once its cooldown has passed.
+    pub fn state(&self) -> CircuitState {
+        let inner = self.inner.lock().expect("circuit breaker lock poisoned");
+
+        match inner.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Makes a call through the breaker. `call` isn't run at all while the breaker is open.
+    pub async fn call<T, E, F, Fut>(&self, call: F) -> Result<T, CircuitBreakerError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        // Held for the duration of a trial call, so that no other calls are let through meanwhile
+        let _trial = self.begin_call()?;
+
+        match tokio::time::timeout(self.call_timeout, call()).await {
+            Ok(Ok(res)) => {
+                self.record_success();
+                Ok(res)
+            }
+            Ok(Err(e)) => {
+                self.record_failure();
+                Err(CircuitBreakerError::Inner(e))
+            }
+            Err(_) => {
+                self.record_failure();
+                Err(CircuitBreakerError::Timeout(self.call_timeout))
+            }
+        }
+    }
+
+    /// Checks whether a call can be made right now, failing fast if the breaker is open.
+    /// While half-open, only one trial call is let through: it's marked as in flight until the
+    /// returned guard is dropped.
+    fn begin_call<E>(&self) -> Result<Option<TrialGuard<'_>>, CircuitBreakerError<E>> {
+        let mut inner = self.inner.lock().expect("circuit breaker lock poisoned");
+
+        let Some(opened_at) = inner.opened_at else {
+            return Ok(None);
+        };
+
+        let remaining = self.cooldown.saturating_sub(opened_at.elapsed());
+        if !remaining.is_zero() || inner.trial_in_flight {
+            return Err(CircuitBreakerError::Open(remaining));
+        }
+
+        inner.trial_in_flight = true;
+        Ok(Some(TrialGuard { breaker: self }))
+    }
+
+    fn record_success(&self) {
+        let mut inner = self.inner.lock().expect("circuit breaker lock poisoned");
+
+        if inner.opened_at.is_some() {
+            tracing::info!("Trial call succeeded, closing circuit breaker");
+        }
+        *inner = BreakerState::default();
+    }
+
+    fn record_failure(&self) {
+        let mut inner = self.inner.lock().expect("circuit breaker lock poisoned");
+        inner.consecutive_failures += 1;
+
+        // A failed trial call (while half-open) re-opens the breaker straight away
+        if inner.opened_at.is_some() || inner.consecutive_failures >= self.failure_threshold {
+            tracing::warn!(
+                consecutive_failures = inner.consecutive_failures,
+                cooldown = ?self.cooldown,
+                "Opening circuit breaker"
+            );
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Marks a trial call as in flight. The mark is cleared when the guard is dropped, so a trial call
+/// that is cancelled (rather than succeeding or failing) doesn't leave the breaker stuck.
+struct TrialGuard<'a> {
+    breaker: &'a CircuitBreaker,
+}
+
+impl Drop for TrialGuard<'_> {
+    fn drop(&mut self) {
+        let mut inner = self
+            .breaker
+            .inner
+            .lock()
+            .expect("circuit breaker lock poisoned");
+        inner.trial_in_flight = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fail() -> Result<(), &'static str> {
+        Err("provider is down")
+    }
+
+    async fn succeed() -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_then_closes_after_a_successful_trial() {
+        let cooldown = Duration::from_secs(10);
+        let breaker = CircuitBreaker::new(2, cooldown, Duration::from_secs(5));
+
+        // Failures below the threshold leave the breaker closed
+        assert!(matches!(
+            breaker.call(fail).await,
+            Err(CircuitBreakerError::Inner(_))
+        ));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // Reaching the threshold opens it, after which calls aren't made at all
+        assert!(breaker.call(fail).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(
+            breaker.call(succeed).await,
+            Err(CircuitBreakerError::Open(remaining)) if remaining == cooldown
+        ));
+
+        // Once the cooldown has passed, a trial call is let through, and closes it on success
+        tokio::time::advance(cooldown).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.call(succeed).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_one_trial_call_is_let_through() {
+        let cooldown = Duration::from_secs(10);
+        let breaker = CircuitBreaker::new(1, cooldown, Duration::from_secs(5));
+
+        assert!(breaker.call(fail).await.is_err());
+        tokio::time::advance(cooldown).await;
+
+        // The trial call is still running when the second call is made
+        let (trial, other) = tokio::join!(
+            breaker.call(|| async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok::<_, &str>(())
+            }),
+            breaker.call(succeed),
+        );
+        assert!(trial.is_ok());
+        assert!(matches!(other, Err(CircuitBreakerError::Open(_))));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_trial_reopens_the_breaker() {
+        let cooldown = Duration::from_secs(10);
+        let breaker = CircuitBreaker::new(1, cooldown, Duration::from_secs(5));
+
+        assert!(breaker.call(fail).await.is_err());
+        tokio::time::advance(cooldown).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        assert!(breaker.call(fail).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
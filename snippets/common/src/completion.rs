@@ -0,0 +1,13 @@
+//! Helpers for reading completion responses.
+use rig::{OneOrMany, agent::Text, message::AssistantContent};
+
+/// The first piece of text in a response's content, if there is one.
+///
+/// Reasoning models (like `gpt-5-mini` on the Responses API) put their reasoning before the text,
+/// so the text isn't necessarily the first item in the response.
+pub fn first_text(choice: &OneOrMany<AssistantContent>) -> Option<&str> {
+    choice.iter().find_map(|content| match content {
+        AssistantContent::Text(Text { text }) => Some(text.as_str()),
+        _ => None,
+    })
+}
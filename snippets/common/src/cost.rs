@@ -0,0 +1,82 @@
+//! Rough cost estimation for completion requests.
+//!
+//! Prices are hardcoded and will go out of date - always check the provider's pricing page
+//! for up-to-date numbers. To add a model, add an entry to `PRICES`.
+
+/// The price of a model, in US dollars per million tokens.
+struct ModelPrice {
+    provider: &'static str,
+    model: &'static str,
+    input_per_million: f64,
+    output_per_million: f64,
+}
+
+const PRICES: &[ModelPrice] = &[
+    ModelPrice {
+        provider: "openai",
+        model: "gpt-5",
+        input_per_million: 1.25,
+        output_per_million: 10.0,
+    },
+    ModelPrice {
+        provider: "openai",
+        model: "gpt-5-mini",
+        input_per_million: 0.25,
+        output_per_million: 2.0,
+    },
+    ModelPrice {
+        provider: "openai",
+        model: "gpt-5-nano",
+        input_per_million: 0.05,
+        output_per_million: 0.4,
+    },
+    ModelPrice {
+        provider: "openai",
+        model: "gpt-5.2",
+        input_per_million: 1.75,
+        output_per_million: 14.0,
+    },
+    ModelPrice {
+        provider: "openai",
+        model: "gpt-4o",
+        input_per_million: 2.5,
+        output_per_million: 10.0,
+    },
+    ModelPrice {
+        provider: "openai",
+        model: "text-embedding-ada-002",
+        input_per_million: 0.1,
+        output_per_million: 0.0,
+    },
+    ModelPrice {
+        provider: "anthropic",
+        model: "claude-3-7-sonnet-latest",
+        input_per_million: 3.0,
+        output_per_million: 15.0,
+    },
+];
+
+/// Estimates the cost (in US dollars) of a request from its token usage.
+/// Unknown models are assumed to be free, and a warning is logged.
+pub fn estimate_cost(
+    provider: &str,
+    model: &str,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+) -> f64 {
+    let Some(price) = PRICES
+        .iter()
+        .find(|price| price.provider == provider && price.model == model)
+    else {
+        tracing::warn!(
+            provider,
+            model,
+            "No price known for model, assuming a cost of $0"
+        );
+        return 0.0;
+    };
+
+    (prompt_tokens as f64 * price.input_per_million
+        + completion_tokens as f64 * price.output_per_million)
+        / 1_000_000.0
+}
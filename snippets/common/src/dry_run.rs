@@ -0,0 +1,110 @@
+//! A dry-run mode for debugging prompts. Set `RIG_DRY_RUN=1` to have the examples that support it
+//! print the fully-assembled request (preamble, history, prompt, documents and so on) as JSON,
+//! rather than sending it to the provider.
+//! Only `rig-api-call` and `rag` support it. `rag` still embeds the query (and any documents that
+//! aren't cached) to retrieve documents, but makes no completion requests.
+use rig::{
+    OneOrMany,
+    completion::{CompletionError, CompletionModel, CompletionRequest, CompletionResponse, Usage},
+    message::AssistantContent,
+    streaming::{RawStreamingChoice, StreamingCompletionResponse},
+};
+use serde_json::json;
+
+/// The response given by `DryRunCompletionModel` in place of a real one.
+const DRY_RUN_RESPONSE: &str = "[dry run: request not sent]";
+
+/// Whether dry-run mode has been turned on with `RIG_DRY_RUN=1`.
+pub fn is_enabled() -> bool {
+    std::env::var("RIG_DRY_RUN").is_ok_and(|value| value == "1")
+}
+
+/// Renders a completion request to the given model as JSON.
+/// Note that this is Rig's provider-agnostic request - each provider converts it into its own
+/// format before sending it (for example, documents are usually turned into a message).
+pub fn request_to_json(model: &str, request: &CompletionRequest) -> serde_json::Value {
+    json!({
+        "model": model,
+        "preamble": request.preamble,
+        "chat_history": request.chat_history,
+        "documents": request.documents,
+        "tools": request.tools,
+        "temperature": request.temperature,
+        "max_tokens": request.max_tokens,
+        "tool_choice": request.tool_choice,
+        "additional_params": request.additional_params,
+    })
+}
+
+/// In dry-run mode, returns the request as pretty-printed JSON, in which case the caller should
+/// print it rather than sending the request. Returns `None` otherwise.
+pub fn maybe_dry_run(model: &str, request: &CompletionRequest) -> Option<String> {
+    if !is_enabled() {
+        return None;
+    }
+
+    Some(to_pretty_json(model, request))
+}
+
+fn to_pretty_json(model: &str, request: &CompletionRequest) -> String {
+    serde_json::to_string_pretty(&request_to_json(model, request)).unwrap_or_else(|e| e.to_string())
+}
+
+/// A completion model that prints every request it's given as JSON, and responds with a placeholder.
+/// Agents can be built on top of it to see the requests they would send, without a network call.
+/// The model name is only used for printing, so it should be the model the request would be sent to.
+#[derive(Clone, Debug)]
+pub struct DryRunCompletionModel {
+    model: String,
+}
+
+impl DryRunCompletionModel {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+        }
+    }
+
+    fn print_request(&self, request: &CompletionRequest) {
+        println!("Dry run request:\n{}", to_pretty_json(&self.model, request));
+    }
+}
+
+impl CompletionModel for DryRunCompletionModel {
+    type Response = ();
+    type StreamingResponse = ();
+    type Client = ();
+
+    fn make(_client: &Self::Client, model: impl Into<String>) -> Self {
+        Self::new(model)
+    }
+
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse<Self::Response>, CompletionError> {
+        self.print_request(&request);
+
+        Ok(CompletionResponse {
+            choice: OneOrMany::one(AssistantContent::text(DRY_RUN_RESPONSE)),
+            usage: Usage::new(),
+            raw_response: (),
+        })
+    }
+
+    async fn stream(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<Self::StreamingResponse>, CompletionError> {
+        self.print_request(&request);
+
+        let chunks = [
+            Ok(RawStreamingChoice::Message(DRY_RUN_RESPONSE.to_string())),
+            Ok(RawStreamingChoice::FinalResponse(())),
+        ];
+
+        Ok(StreamingCompletionResponse::stream(Box::pin(
+            futures::stream::iter(chunks),
+        )))
+    }
+}
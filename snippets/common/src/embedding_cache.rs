@@ -0,0 +1,190 @@
+//! Caches embeddings on disk, keyed by a hash of the embedded text, so that text which has already
+//! been embedded (for example, on a previous run) doesn't need to be sent to the provider again.
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rig::embeddings::{Embedding, EmbeddingError, EmbeddingModel};
+use serde::{Deserialize, Serialize};
+
+/// Embedding vectors, keyed by a hash of the text they were created from.
+/// Vectors from different models can't be compared, so the model's name and number of dimensions
+/// are hashed along with the text. Different models with the same number of dimensions (such as
+/// `text-embedding-ada-002` and `text-embedding-3-small`) can then safely share a cache.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EmbeddingCache {
+    embeddings: HashMap<u64, Vec<f64>>,
+}
+
+impl EmbeddingCache {
+    /// Loads a cache from a JSON file, or creates an empty one if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Saves the cache to a JSON file.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Hashes some text (along with the embedding model and its dimensions) into a cache key.
+    /// Note that `DefaultHasher` is only guaranteed to be stable within the same Rust version,
+    /// so upgrading Rust may cause a one-off cache miss for everything.
+    fn key(model: &str, text: &str, ndims: usize) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        model.hash(&mut hasher);
+        text.hash(&mut hasher);
+        ndims.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn get(&self, model: &str, text: &str, ndims: usize) -> Option<&Vec<f64>> {
+        self.embeddings.get(&Self::key(model, text, ndims))
+    }
+
+    pub fn insert(&mut self, model: &str, text: &str, ndims: usize, vec: Vec<f64>) {
+        self.embeddings.insert(Self::key(model, text, ndims), vec);
+    }
+
+    pub fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.embeddings.is_empty()
+    }
+}
+
+/// Wraps an embedding model so that the cache is checked before anything is sent to the provider.
+/// Only texts that aren't in the cache are embedded, and their embeddings are added to it.
+/// Call `save` to persist the cache for the next run.
+#[derive(Clone, Debug)]
+pub struct CachedEmbeddingModel<M> {
+    inner: M,
+    /// The name of the wrapped model, used as part of each cache key
+    model: String,
+    cache: Arc<Mutex<EmbeddingCache>>,
+    path: PathBuf,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+}
+
+impl<M> CachedEmbeddingModel<M> {
+    /// Wraps the model (named `model`), loading the cache from the given path (if it exists).
+    pub fn load(
+        inner: M,
+        model: impl Into<String>,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.into();
+        let cache = EmbeddingCache::load(&path)?;
+
+        Ok(Self {
+            inner,
+            model: model.into(),
+            cache: Arc::new(Mutex::new(cache)),
+            path,
+            hits: Arc::new(AtomicUsize::new(0)),
+            misses: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Saves the cache to the path it was loaded from.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.cache
+            .lock()
+            .expect("embedding cache lock poisoned")
+            .save(&self.path)
+    }
+
+    /// How many texts have been served from the cache, and how many had to be embedded.
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl<M: EmbeddingModel> EmbeddingModel for CachedEmbeddingModel<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    type Client = M::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>, dims: Option<usize>) -> Self {
+        let model = model.into();
+        Self {
+            inner: M::make(client, model.clone(), dims),
+            model,
+            cache: Arc::default(),
+            path: PathBuf::default(),
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        let texts: Vec<String> = texts.into_iter().collect();
+        let ndims = self.ndims();
+
+        // Look up every text, keeping track of which ones still need embedding.
+        // The lock is released before making the request, so it's never held across an await.
+        let mut vecs: Vec<Option<Vec<f64>>> = {
+            let cache = self.cache.lock().expect("embedding cache lock poisoned");
+            texts
+                .iter()
+                .map(|text| cache.get(&self.model, text, ndims).cloned())
+                .collect()
+        };
+        let missing: Vec<String> = texts
+            .iter()
+            .zip(&vecs)
+            .filter(|(_, vec)| vec.is_none())
+            .map(|(text, _)| text.clone())
+            .collect();
+
+        self.hits
+            .fetch_add(texts.len() - missing.len(), Ordering::Relaxed);
+        self.misses.fetch_add(missing.len(), Ordering::Relaxed);
+
+        if !missing.is_empty() {
+            let mut embedded = self.inner.embed_texts(missing).await?.into_iter();
+            let mut cache = self.cache.lock().expect("embedding cache lock poisoned");
+
+            for (text, vec) in texts.iter().zip(vecs.iter_mut()) {
+                if vec.is_none() {
+                    let embedding = embedded.next().ok_or_else(|| {
+                        EmbeddingError::ResponseError(
+                            "Provider returned fewer embeddings than requested".to_string(),
+                        )
+                    })?;
+                    cache.insert(&self.model, text, ndims, embedding.vec.clone());
+                    *vec = Some(embedding.vec);
+                }
+            }
+        }
+
+        Ok(texts
+            .into_iter()
+            .zip(vecs)
+            .map(|(document, vec)| Embedding {
+                document,
+                vec: vec.expect("every missing embedding was filled in above"),
+            })
+            .collect())
+    }
+}
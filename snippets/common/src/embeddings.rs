@@ -0,0 +1,60 @@
+//! Guards against mixing embeddings from different models in the same vector store.
+//! Vectors of different lengths can't be meaningfully compared, but the in-memory vector store
+//! will accept them anyway and return garbage scores.
+use rig::embeddings::Embedding;
+
+/// Returned when an embedding doesn't have the same number of dimensions as the ones before it.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Embedding for `{document}` has {actual} dimensions, but {expected} were expected. Are two different embedding models being mixed?"
+)]
+pub struct DimensionMismatchError {
+    pub document: String,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+/// Records the dimension of the first embedding it sees, then checks every later embedding against it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DimensionValidator {
+    expected: Option<usize>,
+}
+
+impl DimensionValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The dimension every embedding is expected to have, if any embeddings have been checked yet.
+    pub fn expected(&self) -> Option<usize> {
+        self.expected
+    }
+
+    /// Checks a single embedding. The first embedding checked sets the expected dimension.
+    pub fn check(&mut self, embedding: &Embedding) -> Result<(), DimensionMismatchError> {
+        let actual = embedding.vec.len();
+
+        match self.expected {
+            None => {
+                self.expected = Some(actual);
+                Ok(())
+            }
+            Some(expected) if expected == actual => Ok(()),
+            Some(expected) => Err(DimensionMismatchError {
+                document: embedding.document.clone(),
+                expected,
+                actual,
+            }),
+        }
+    }
+
+    /// Checks each of the given embeddings in turn, stopping at the first mismatch.
+    pub fn check_all<'a>(
+        &mut self,
+        embeddings: impl IntoIterator<Item = &'a Embedding>,
+    ) -> Result<(), DimensionMismatchError> {
+        embeddings
+            .into_iter()
+            .try_for_each(|embedding| self.check(embedding))
+    }
+}
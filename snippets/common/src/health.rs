@@ -0,0 +1,45 @@
+//! Checks that a provider client is usable before running an example, so that a misconfigured
+//! API key is reported straight away rather than partway through a run.
+use rig::{
+    client::CompletionClient,
+    completion::{CompletionError, CompletionModel},
+};
+
+/// Returned when a provider client couldn't be verified.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Could not reach the provider using model `{model}`. Check that your API key is set correctly.\nCaused by: {source}"
+)]
+pub struct VerifyClientError {
+    model: String,
+    #[source]
+    source: CompletionError,
+}
+
+/// Sends a minimal completion request using the given model to confirm the client's credentials work.
+/// This costs a handful of tokens.
+pub async fn verify_client<C: CompletionClient>(
+    client: &C,
+    model: &str,
+) -> Result<(), VerifyClientError> {
+    client
+        .completion_model(model)
+        .completion_request("Reply with OK.")
+        .send()
+        .await
+        .map_err(|source| VerifyClientError {
+            model: model.to_string(),
+            source,
+        })?;
+
+    Ok(())
+}
+
+/// Verifies the client with [`verify_client`], printing the error and exiting the process if it fails.
+/// Examples call this first so that a misconfigured API key is reported before any other output.
+pub async fn verify_client_or_exit<C: CompletionClient>(client: &C, model: &str) {
+    if let Err(e) = verify_client(client, model).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,14 @@
+//! Small utilities shared between the examples in this workspace.
+pub mod circuit_breaker;
+pub mod completion;
+pub mod cost;
+pub mod dry_run;
+pub mod embedding_cache;
+pub mod embeddings;
+pub mod health;
+pub mod limits;
+pub mod mock;
+pub mod models;
+pub mod retry;
+pub mod telemetry;
+pub mod tokens;
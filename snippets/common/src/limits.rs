@@ -0,0 +1,42 @@
+//! Caps on how many tokens a model may generate, so that a verbose response can't run up an
+//! unexpectedly large bill. Set `RIG_MAX_TOKENS` to cap the output of every example that supports it.
+use rig::agent::{AgentBuilder, AgentBuilderSimple};
+use rig::completion::CompletionModel;
+
+/// The output token cap set with `RIG_MAX_TOKENS`, if any.
+/// An invalid value is ignored (with a warning) rather than failing the example.
+pub fn max_output_tokens() -> Option<u64> {
+    let value = std::env::var("RIG_MAX_TOKENS").ok()?;
+
+    match value.parse() {
+        Ok(max_tokens) => Some(max_tokens),
+        Err(e) => {
+            tracing::warn!(%value, error = %e, "Ignoring invalid RIG_MAX_TOKENS");
+            None
+        }
+    }
+}
+
+/// Sets an optional cap on output tokens, so that callers don't need to branch on whether a cap is set.
+/// Note that for reasoning models, reasoning tokens count towards the cap too.
+pub trait WithMaxTokens {
+    fn with_max_tokens(self, max_tokens: Option<u64>) -> Self;
+}
+
+impl<M: CompletionModel> WithMaxTokens for AgentBuilder<M> {
+    fn with_max_tokens(self, max_tokens: Option<u64>) -> Self {
+        match max_tokens {
+            Some(max_tokens) => self.max_tokens(max_tokens),
+            None => self,
+        }
+    }
+}
+
+impl<M: CompletionModel> WithMaxTokens for AgentBuilderSimple<M> {
+    fn with_max_tokens(self, max_tokens: Option<u64>) -> Self {
+        match max_tokens {
+            Some(max_tokens) => self.max_tokens(max_tokens),
+            None => self,
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Mock models that return canned (or deterministic) results without calling a provider.
+//! Set `RIG_MOCK=1` to have the examples that support it use these instead of a real provider,
+//! so they can be run offline or in CI without an API key.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rig::{
+    OneOrMany,
+    completion::{CompletionError, CompletionModel, CompletionRequest, CompletionResponse, Usage},
+    embeddings::{Embedding, EmbeddingError, EmbeddingModel},
+    message::AssistantContent,
+    streaming::{RawStreamingChoice, RawStreamingToolCall, StreamingCompletionResponse},
+};
+
+/// Whether mock mode has been turned on with `RIG_MOCK=1`.
+pub fn is_enabled() -> bool {
+    std::env::var("RIG_MOCK").is_ok_and(|value| value == "1")
+}
+
+/// A completion model that always responds with the same text (or tool call), whatever the request.
+/// Token usage is always reported as zero.
+#[derive(Clone, Debug)]
+pub struct MockCompletionModel {
+    response: AssistantContent,
+}
+
+impl MockCompletionModel {
+    pub fn new(response: impl Into<String>) -> Self {
+        Self {
+            response: AssistantContent::text(response),
+        }
+    }
+
+    /// A model that always responds by calling the given tool, rather than with text.
+    /// Useful for seeing how code that expects a text response copes without one.
+    pub fn with_tool_call(name: impl Into<String>, arguments: serde_json::Value) -> Self {
+        Self {
+            response: AssistantContent::tool_call("mock_tool_call", name, arguments),
+        }
+    }
+}
+
+impl Default for MockCompletionModel {
+    fn default() -> Self {
+        Self::new("This is a mock response.")
+    }
+}
+
+impl CompletionModel for MockCompletionModel {
+    type Response = ();
+    type StreamingResponse = ();
+    type Client = ();
+
+    fn make(_client: &Self::Client, _model: impl Into<String>) -> Self {
+        Self::default()
+    }
+
+    async fn completion(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<CompletionResponse<Self::Response>, CompletionError> {
+        Ok(CompletionResponse {
+            choice: OneOrMany::one(self.response.clone()),
+            usage: Usage::new(),
+            raw_response: (),
+        })
+    }
+
+    /// Streams the canned response one word at a time (or a tool call in one go).
+    async fn stream(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<Self::StreamingResponse>, CompletionError> {
+        let choices: Vec<_> = match &self.response {
+            AssistantContent::Text(text) => text
+                .text()
+                .split_inclusive(' ')
+                .map(|word| RawStreamingChoice::Message(word.to_string()))
+                .collect(),
+            AssistantContent::ToolCall(tool_call) => {
+                vec![RawStreamingChoice::ToolCall(RawStreamingToolCall {
+                    id: tool_call.id.clone(),
+                    call_id: tool_call.call_id.clone(),
+                    name: tool_call.function.name.clone(),
+                    arguments: tool_call.function.arguments.clone(),
+                    signature: None,
+                    additional_params: None,
+                })]
+            }
+            _ => Vec::new(),
+        };
+
+        let chunks: Vec<_> = choices
+            .into_iter()
+            .chain(std::iter::once(RawStreamingChoice::FinalResponse(())))
+            .map(Ok)
+            .collect();
+
+        Ok(StreamingCompletionResponse::stream(Box::pin(
+            futures::stream::iter(chunks),
+        )))
+    }
+}
+
+/// An embedding model that embeds text by hashing each of its words into one of `ndims` buckets.
+/// Texts that share words end up with similar embeddings, which is enough for semantic search
+/// examples to give sensible (and repeatable) results.
+#[derive(Clone, Debug)]
+pub struct MockEmbeddingModel {
+    ndims: usize,
+}
+
+impl MockEmbeddingModel {
+    pub fn new(ndims: usize) -> Self {
+        Self {
+            ndims: ndims.max(1),
+        }
+    }
+
+    fn embed(&self, text: &str) -> Embedding {
+        let mut vec = vec![0.0; self.ndims];
+
+        for word in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            let mut hasher = DefaultHasher::new();
+            word.to_lowercase().hash(&mut hasher);
+            vec[hasher.finish() as usize % self.ndims] += 1.0;
+        }
+
+        Embedding {
+            document: text.to_string(),
+            vec,
+        }
+    }
+}
+
+impl Default for MockEmbeddingModel {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl EmbeddingModel for MockEmbeddingModel {
+    const MAX_DOCUMENTS: usize = 1024;
+
+    type Client = ();
+
+    fn make(_client: &Self::Client, _model: impl Into<String>, dims: Option<usize>) -> Self {
+        dims.map(Self::new).unwrap_or_default()
+    }
+
+    fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        Ok(texts.into_iter().map(|text| self.embed(&text)).collect())
+    }
+}
+
+/// Wraps an embedding model so that its first `failures` requests fail with a rate limit error,
+/// as if the provider had returned a 429. Later requests are passed through to the wrapped model.
+/// Useful for seeing retries in action.
+#[derive(Clone, Debug)]
+pub struct FlakyEmbeddingModel<M> {
+    inner: M,
+    failures: usize,
+    attempts: Arc<AtomicUsize>,
+}
+
+impl<M> FlakyEmbeddingModel<M> {
+    pub fn new(inner: M, failures: usize) -> Self {
+        Self {
+            inner,
+            failures,
+            attempts: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<M: EmbeddingModel> EmbeddingModel for FlakyEmbeddingModel<M> {
+    const MAX_DOCUMENTS: usize = M::MAX_DOCUMENTS;
+
+    type Client = M::Client;
+
+    fn make(client: &Self::Client, model: impl Into<String>, dims: Option<usize>) -> Self {
+        Self::new(M::make(client, model, dims), 0)
+    }
+
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String> + Send,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Err(EmbeddingError::ProviderError(
+                "429 Too Many Requests: rate limit exceeded".to_string(),
+            ));
+        }
+
+        self.inner.embed_texts(texts).await
+    }
+}
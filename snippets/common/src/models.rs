@@ -0,0 +1,30 @@
+//! The models used by the examples, named after the role they play in them.
+//!
+//! Bumping a model here updates every example that uses it, so prefer these constants
+//! over writing out model names by hand.
+
+/// Models from OpenAI.
+pub mod openai {
+    use rig::providers::openai::TEXT_EMBEDDING_ADA_002;
+
+    /// The main model, used to answer the user's questions.
+    pub const CHAT_MODEL: &str = "gpt-5.2";
+
+    /// The model the examples' agents run on (such as routing targets and multi-agent workers).
+    pub const AGENT_MODEL: &str = "gpt-5";
+
+    /// A cheaper chat model, for simpler questions and background tasks
+    /// (like summarizing or reranking) that don't need the main model.
+    pub const SMALL_CHAT_MODEL: &str = "gpt-5-mini";
+
+    /// The smallest and cheapest chat model, for when a rough answer is good enough.
+    pub const TINY_CHAT_MODEL: &str = "gpt-5-nano";
+
+    /// The model used to pick a route for a query. Routing only needs a route name back
+    /// (or a short JSON decision with a one-sentence reason), so a small model is plenty.
+    pub const ROUTER_MODEL: &str = "gpt-5-mini";
+
+    /// The model used to embed documents and queries.
+    /// Embeddings from different models can't be compared, so this must be the same for both.
+    pub const EMBED_MODEL: &str = TEXT_EMBEDDING_ADA_002;
+}
@@ -0,0 +1,104 @@
+//! Retries for requests that fail for transient reasons (rate limits, overloaded servers, dropped
+//! connections), using exponential backoff.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rig::embeddings::EmbeddingError;
+use rig::vector_store::VectorStoreError;
+
+/// Exponential backoff starting at 500ms, with up to 250ms of random jitter so that
+/// multiple clients don't all retry at exactly the same time.
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let base = Duration::from_millis(500) * 2u32.pow(attempt);
+    // We avoid pulling in a random number crate by using the current time as a cheap source of jitter
+    let jitter_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() % 250)
+        .unwrap_or(0);
+
+    base + Duration::from_millis(jitter_ms as u64)
+}
+
+/// Whether a provider's error message describes a transient failure.
+/// Providers return errors as text, so we check the message for known rate limit/overload errors.
+pub fn is_transient_message(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    let is_auth_error = msg.contains("api key") || msg.contains("unauthorized");
+    let is_transient = msg.contains("rate limit")
+        || msg.contains("rate_limit")
+        || msg.contains("429")
+        || msg.contains("timeout")
+        || msg.contains("overloaded")
+        || msg.contains("server_error")
+        || msg.contains("api_error");
+
+    is_transient && !is_auth_error
+}
+
+/// Whether an embedding error is likely to be transient (and therefore worth retrying).
+pub fn is_retryable_embedding_error(error: &EmbeddingError) -> bool {
+    match error {
+        // Connection errors, timeouts, etc.
+        EmbeddingError::HttpError(_) => true,
+        EmbeddingError::ProviderError(msg) => is_transient_message(msg),
+        _ => false,
+    }
+}
+
+/// Whether a vector search error is likely to be transient. Searches embed the query first,
+/// so this is the case when embedding the query failed for a transient reason.
+pub fn is_retryable_search_error(error: &VectorStoreError) -> bool {
+    matches!(error, VectorStoreError::EmbeddingError(e) if is_retryable_embedding_error(e))
+}
+
+/// Runs an embedding request, retrying transient failures up to `max_retries` times with
+/// exponential backoff. `embed` is called once per attempt, so it should create a fresh request each time.
+pub async fn retry_embed<T, F, Fut>(max_retries: u32, embed: F) -> Result<T, EmbeddingError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, EmbeddingError>>,
+{
+    retry(max_retries, is_retryable_embedding_error, embed).await
+}
+
+/// Runs a vector search, retrying transient failures to embed the query up to `max_retries` times
+/// with exponential backoff. `search` is called once per attempt, so it should create a fresh search each time.
+pub async fn retry_search<T, F, Fut>(max_retries: u32, search: F) -> Result<T, VectorStoreError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, VectorStoreError>>,
+{
+    retry(max_retries, is_retryable_search_error, search).await
+}
+
+/// Runs a request, retrying any failure that `is_retryable` accepts up to `max_retries` times with
+/// exponential backoff. `request` is called once per attempt, so it should create a fresh request each time.
+pub async fn retry<T, E, F, Fut>(
+    max_retries: u32,
+    is_retryable: impl Fn(&E) -> bool,
+    mut request: F,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match request().await {
+            Ok(res) => return Ok(res),
+            Err(e) if attempt < max_retries && is_retryable(&e) => {
+                let delay = backoff_delay(attempt);
+                tracing::warn!(
+                    attempt = attempt + 1,
+                    error = %e,
+                    ?delay,
+                    "Request failed, retrying"
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
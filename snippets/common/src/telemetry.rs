@@ -0,0 +1,154 @@
+//! Tracing setup that can be shared between examples, so each `main` doesn't need to build its own subscriber.
+//!
+//! The log level can be changed with the `RUST_LOG` environment variable (defaults to `info`).
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    trace::{Sampler, SdkTracerProvider},
+};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// How logs and spans should be output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable, multi-line output to `stdout`.
+    Pretty,
+    /// One JSON object per line, for log aggregators.
+    Json,
+    /// Pretty output to `stdout`, with spans also exported to an OpenTelemetry collector over OTLP/HTTP.
+    /// The collector and sampling ratio are configured with environment variables
+    /// (see `OtlpConfig::from_env` and `sampler_from_env`).
+    Otel,
+}
+
+/// Keeps the OpenTelemetry pipeline (if any) alive. Remaining spans are flushed when this is dropped,
+/// so hold onto it until the end of `main`.
+#[must_use = "spans may not be exported if the guard is dropped immediately"]
+pub struct TelemetryGuard {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+/// Installs a global tracing subscriber using the given output format.
+/// This should only be called once, at the start of `main`.
+pub fn init(format: LogFormat) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+    init_with_default_filter(format, "info")
+}
+
+/// The same as `init`, but with a different filter for when `RUST_LOG` isn't set,
+/// for example `"info,rig=trace"` to see Rig's own spans.
+pub fn init_with_default_filter(
+    format: LogFormat,
+    default_filter: &str,
+) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+    let filter_layer =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let registry = tracing_subscriber::registry().with(filter_layer);
+
+    match format {
+        LogFormat::Pretty => {
+            registry
+                .with(tracing_subscriber::fmt::layer().pretty())
+                .try_init()?;
+        }
+        LogFormat::Json => {
+            registry
+                .with(tracing_subscriber::fmt::layer().json())
+                .try_init()?;
+        }
+        LogFormat::Otel => {
+            let otlp_config = OtlpConfig::from_env();
+            let mut exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_protocol(otlp_config.protocol);
+            if let Some(endpoint) = otlp_config.signal_endpoint("traces") {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter.build()?)
+                .with_resource(Resource::builder().with_service_name("rig-service").build())
+                .with_sampler(sampler_from_env())
+                .build();
+            let tracer = provider.tracer("example");
+
+            registry
+                .with(tracing_subscriber::fmt::layer().pretty())
+                .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                .try_init()?;
+
+            return Ok(TelemetryGuard {
+                tracer_provider: Some(provider),
+            });
+        }
+    }
+
+    Ok(TelemetryGuard {
+        tracer_provider: None,
+    })
+}
+
+/// Creates a sampler using the ratio set in the `OTEL_SAMPLE_RATIO` environment variable.
+/// A ratio of `0` exports no spans and `1` exports all of them (the default if unset or invalid).
+///
+/// The sampler is parent-based, so child spans always follow the decision made for the root span
+/// rather than traces being partially exported.
+///
+/// For example, `OTEL_SAMPLE_RATIO=0 cargo run --bin otel` will not send any spans to the collector.
+pub fn sampler_from_env() -> Sampler {
+    let ratio = std::env::var("OTEL_SAMPLE_RATIO")
+        .ok()
+        .and_then(|ratio| ratio.parse::<f64>().ok())
+        .map(|ratio| ratio.clamp(0.0, 1.0))
+        .unwrap_or(1.0);
+
+    Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+}
+
+/// Where (and how) spans and metrics are sent to the OTel collector.
+pub struct OtlpConfig {
+    /// The collector's base URL, or `None` to use the default (`http://localhost:4318`).
+    pub endpoint: Option<String>,
+    pub protocol: Protocol,
+}
+
+impl OtlpConfig {
+    /// Reads the config from the standard `OTEL_EXPORTER_OTLP_ENDPOINT` and
+    /// `OTEL_EXPORTER_OTLP_PROTOCOL` environment variables.
+    /// Only the HTTP protocols (`http/protobuf`, the default, and `http/json`) are supported.
+    ///
+    /// For example, to send telemetry to a collector running elsewhere:
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT=http://collector.internal:4318 cargo run --bin otel`
+    pub fn from_env() -> Self {
+        let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .ok()
+            .filter(|endpoint| !endpoint.is_empty());
+
+        let protocol = match std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL").as_deref() {
+            Ok("http/json") => Protocol::HttpJson,
+            Ok("http/protobuf") | Err(_) => Protocol::HttpBinary,
+            Ok(other) => {
+                // This is read before the subscriber is installed, so it can't be logged with `tracing`
+                eprintln!("Warning: unsupported OTLP protocol {other:?}, using http/protobuf");
+                Protocol::HttpBinary
+            }
+        };
+
+        Self { endpoint, protocol }
+    }
+
+    /// The URL to send a given signal (eg, `traces`) to. When the endpoint is set explicitly,
+    /// the exporter uses it as-is, so the signal's path has to be added here.
+    pub fn signal_endpoint(&self, signal: &str) -> Option<String> {
+        self.endpoint
+            .as_ref()
+            .map(|endpoint| format!("{}/v1/{signal}", endpoint.trim_end_matches('/')))
+    }
+}
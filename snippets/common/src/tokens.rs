@@ -0,0 +1,25 @@
+//! Token counting, for budgeting context windows and estimating costs before sending a request.
+use tiktoken_rs::{CoreBPE, cl100k_base_singleton, o200k_base_singleton};
+
+/// Counts the number of tokens in some text, using the tokenizer for the given model.
+/// Unknown models fall back to the tokenizer used by current OpenAI models, so the count
+/// should be treated as an estimate for models from other providers.
+pub fn count_tokens(text: &str, model: &str) -> usize {
+    encoding_for_model(model)
+        .encode_with_special_tokens(text)
+        .len()
+}
+
+/// Maps a model name to its tokenizer.
+fn encoding_for_model(model: &str) -> &'static CoreBPE {
+    if model.starts_with("gpt-4-")
+        || model == "gpt-4"
+        || model.starts_with("gpt-3.5")
+        || model.starts_with("text-embedding")
+    {
+        cl100k_base_singleton()
+    } else {
+        // gpt-4o, gpt-4.1, gpt-5 and the o-series models all use o200k_base
+        o200k_base_singleton()
+    }
+}
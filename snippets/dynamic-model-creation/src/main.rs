@@ -1,15 +1,20 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Instant;
 
+use common::retry::{is_transient_message, retry};
+use futures::future::join_all;
 use rig::agent::Agent;
-use rig::client::{CompletionClient, ProviderClient};
-use rig::completion::{Prompt, PromptError};
+use rig::client::CompletionClient;
+use rig::completion::{CompletionError, Prompt, PromptError};
+use rig::http_client;
 use rig::providers::anthropic::completion::CLAUDE_3_7_SONNET;
 use rig::providers::openai::GPT_4O;
 use rig::providers::{anthropic, openai};
 
 #[tokio::main]
 async fn main() {
-    let registry = ProviderRegistry::new();
+    let mut registry = ProviderRegistry::new();
 
     let prompt = "How much does 4oz of parmesan cheese weigh?";
     println!("Prompt: {prompt}");
@@ -19,7 +24,7 @@ async fn main() {
         preamble: "You are a helpful assistant",
     };
 
-    let openai_agent = registry.agent("openai", &helpful_cfg).unwrap();
+    let openai_agent = registry.agent(RegistryKey::OpenAi, &helpful_cfg).unwrap();
 
     let oai_response = openai_agent.prompt(prompt).await.unwrap();
     println!("Helpful response (OpenAI): {oai_response}");
@@ -29,10 +34,58 @@ async fn main() {
         preamble: "You are an unhelpful assistant",
     };
 
-    let anthropic_agent = registry.agent("anthropic", &unhelpful_cfg).unwrap();
+    // Keys can also be parsed from strings (for example, when read from a config file)
+    let anthropic_key: RegistryKey = "anthropic".parse().unwrap();
+    let anthropic_agent = registry.agent(anthropic_key, &unhelpful_cfg).unwrap();
 
     let anthropic_response = anthropic_agent.prompt(prompt).await.unwrap();
     println!("Unhelpful response (Anthropic): {anthropic_response}");
+
+    // Ask every provider the same question at once, to compare their answers and latencies
+    compare_providers(
+        &registry,
+        &[RegistryKey::OpenAi, RegistryKey::Anthropic],
+        &helpful_cfg,
+        prompt,
+    )
+    .await;
+
+    // Simulate an outage of our preferred provider by giving it an invalid API key.
+    // The fallback chain skips over it and uses the next provider that passes its health check.
+    registry.register(RegistryKey::Anthropic, anthropic_agent_with_invalid_key);
+
+    let (key, fallback_agent) = registry
+        .agent_with_fallback(&["anthropic", "openai"], &helpful_cfg)
+        .await
+        .unwrap();
+
+    let fallback_response = fallback_agent.prompt(prompt).await.unwrap();
+    println!("Helpful response (fallback to {key:?}): {fallback_response}");
+
+    // Errors are classified so we know whether retrying is worthwhile.
+    // Providers report most errors as text, so the same error can arrive in different shapes.
+    let errors = [
+        CompletionError::ProviderError(
+            r#"{"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your per-minute rate limit"}}"#.to_string(),
+        ),
+        CompletionError::ProviderError(
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#.to_string(),
+        ),
+        CompletionError::HttpError(http_client::Error::InvalidStatusCode(
+            reqwest::StatusCode::UNAUTHORIZED,
+        )),
+        CompletionError::ResponseError("Response contained no message or tool call".to_string()),
+    ];
+    for error in errors {
+        let error = PromptError::CompletionError(error);
+        println!("{:?}: {error}", classify_error(&error));
+    }
+
+    // An invalid API key is an auth error, so it fails straight away rather than being retried
+    let invalid_agent = anthropic_agent_with_invalid_key(&helpful_cfg).unwrap();
+    if let Err(e) = prompt_with_retry(&invalid_agent, prompt, 3).await {
+        println!("Prompt failed ({:?}): {e}", classify_error(&e));
+    }
 }
 
 enum Agents {
@@ -47,6 +100,144 @@ impl Agents {
             Self::OpenAI(agent) => agent.prompt(prompt).await,
         }
     }
+
+    /// Sends a minimal prompt to check that the provider is reachable and the API key works.
+    async fn health_check(&self) -> Result<(), PromptError> {
+        self.prompt("Reply with OK.").await.map(|_| ())
+    }
+}
+
+/// Sends the same prompt to each provider concurrently, then prints a table of how long each one
+/// took and how long its response was, followed by the responses themselves.
+/// Providers whose agent can't be created (for example, because their API key isn't set) are skipped.
+async fn compare_providers(
+    registry: &ProviderRegistry,
+    providers: &[RegistryKey],
+    agent_config: &AgentConfig<'_>,
+    prompt: &str,
+) {
+    let agents: Vec<(RegistryKey, Agents)> = providers
+        .iter()
+        .filter_map(|&key| match registry.agent(key, agent_config) {
+            Some(agent) => Some((key, agent)),
+            None => {
+                println!("Could not create agent for {key:?}, skipping");
+                None
+            }
+        })
+        .collect();
+
+    let results = join_all(agents.iter().map(|(key, agent)| async move {
+        let start = Instant::now();
+        let response = agent.prompt(prompt).await;
+        (*key, start.elapsed(), response)
+    }))
+    .await;
+
+    println!(
+        "{:<12} {:>10} {:>16}",
+        "Provider", "Latency", "Response length"
+    );
+    for (key, latency, response) in &results {
+        let length = match response {
+            Ok(response) => format!("{} chars", response.chars().count()),
+            Err(_) => "error".to_string(),
+        };
+        println!(
+            "{:<12} {:>8}ms {:>16}",
+            format!("{key:?}"),
+            latency.as_millis(),
+            length
+        );
+    }
+
+    for (key, _, response) in results {
+        match response {
+            Ok(response) => println!("[{key:?}] {response}"),
+            Err(e) => println!("[{key:?}] Failed: {e}"),
+        }
+    }
+}
+
+/// How an error from `Agents::prompt` should be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorClass {
+    /// The provider is rate limiting us. Worth retrying, after backing off.
+    RateLimited,
+    /// The API key is invalid or lacks permission. Retrying won't help, but another provider might.
+    Auth,
+    /// A temporary failure, like a dropped connection or an overloaded server. Worth retrying.
+    Transient,
+    /// Anything else, like a malformed request. Retrying won't help.
+    Fatal,
+}
+
+/// Classifies an error from any provider, so callers can decide whether to retry it.
+fn classify_error(error: &PromptError) -> ErrorClass {
+    let PromptError::CompletionError(error) = error else {
+        // Tool errors and the like come from our own code rather than the provider
+        return ErrorClass::Fatal;
+    };
+
+    match error {
+        CompletionError::HttpError(http_client::Error::InvalidStatusCode(status))
+        | CompletionError::HttpError(http_client::Error::InvalidStatusCodeWithMessage(status, _)) => {
+            match status.as_u16() {
+                429 => ErrorClass::RateLimited,
+                401 | 403 => ErrorClass::Auth,
+                408 | 500.. => ErrorClass::Transient,
+                _ => ErrorClass::Fatal,
+            }
+        }
+        // Connection errors, timeouts, etc.
+        CompletionError::HttpError(
+            http_client::Error::Instance(_) | http_client::Error::StreamEnded,
+        ) => ErrorClass::Transient,
+        CompletionError::ProviderError(msg) => classify_provider_message(msg),
+        _ => ErrorClass::Fatal,
+    }
+}
+
+/// Providers return most errors as the response body's text, so we look for the error types
+/// (and messages) that OpenAI and Anthropic use. Transient errors are recognised by the same check
+/// as the other examples use.
+fn classify_provider_message(msg: &str) -> ErrorClass {
+    let lower = msg.to_lowercase();
+    let contains_any = |needles: &[&str]| needles.iter().any(|needle| lower.contains(needle));
+
+    if contains_any(&[
+        "authentication_error",
+        "permission_error",
+        "invalid_api_key",
+        "invalid x-api-key",
+        "incorrect api key",
+        "unauthorized",
+    ]) {
+        ErrorClass::Auth
+    } else if contains_any(&["rate_limit", "rate limit", "429"]) {
+        ErrorClass::RateLimited
+    } else if is_transient_message(msg) {
+        ErrorClass::Transient
+    } else {
+        ErrorClass::Fatal
+    }
+}
+
+/// Prompts the agent, retrying up to `max_retries` times (with exponential backoff) if the error
+/// is worth retrying.
+async fn prompt_with_retry(
+    agent: &Agents,
+    prompt: &str,
+    max_retries: u32,
+) -> Result<String, PromptError> {
+    let is_retryable = |error: &PromptError| {
+        matches!(
+            classify_error(error),
+            ErrorClass::RateLimited | ErrorClass::Transient
+        )
+    };
+
+    retry(max_retries, is_retryable, || agent.prompt(prompt)).await
 }
 
 struct AgentConfig<'a> {
@@ -54,13 +245,79 @@ struct AgentConfig<'a> {
     preamble: &'a str,
 }
 
-// In production you would likely want to create some sort of `RegistryKey` type instead of
-// allowing arbitrary strings, for improved type safety
-struct ProviderRegistry(HashMap<&'static str, fn(&AgentConfig) -> Agents>);
+/// A typed key for looking up providers in the `ProviderRegistry`.
+/// Using an enum over arbitrary strings means typos are caught at compile time.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+enum RegistryKey {
+    OpenAi,
+    Anthropic,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown provider: {0}")]
+struct UnknownProvider(String);
+
+impl FromStr for RegistryKey {
+    type Err = UnknownProvider;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(Self::OpenAi),
+            "anthropic" => Ok(Self::Anthropic),
+            other => Err(UnknownProvider(other.to_string())),
+        }
+    }
+}
+
+/// Errors that can occur while creating an agent from the registry
+#[derive(Debug, thiserror::Error)]
+enum ProviderError {
+    #[error("Environment variable {0} is not set")]
+    MissingApiKey(&'static str),
+    #[error(transparent)]
+    Client(#[from] rig::http_client::Error),
+}
+
+/// Errors that can occur while looking for a working provider in a fallback chain
+#[derive(Debug, thiserror::Error)]
+enum FallbackError {
+    #[error(transparent)]
+    UnknownProvider(#[from] UnknownProvider),
+    #[error("None of the providers in the fallback chain are available")]
+    NoneAvailable,
+}
+
+/// A function that creates an agent for a given provider.
+/// This fails if the provider's client can't be created (for example, if its API key isn't set).
+type AgentFactory = fn(&AgentConfig) -> Result<Agents, ProviderError>;
+
+struct ProviderRegistry(HashMap<RegistryKey, AgentFactory>);
+
+/// Reads an API key from the given environment variable
+fn api_key(var: &'static str) -> Result<String, ProviderError> {
+    std::env::var(var).map_err(|_| ProviderError::MissingApiKey(var))
+}
 
 /// A function that creates an instance of `Agents` (using the Anthropic variant)
-fn anthropic_agent(AgentConfig { name, preamble }: &AgentConfig) -> Agents {
-    let agent = anthropic::Client::from_env()
+fn anthropic_agent(agent_config: &AgentConfig) -> Result<Agents, ProviderError> {
+    let client = anthropic::Client::new(api_key("ANTHROPIC_API_KEY")?)?;
+
+    Ok(build_anthropic_agent(client, agent_config))
+}
+
+/// The same as `anthropic_agent`, but with an API key that the provider will reject.
+/// Used to demonstrate falling back to another provider.
+fn anthropic_agent_with_invalid_key(agent_config: &AgentConfig) -> Result<Agents, ProviderError> {
+    let client = anthropic::Client::new("invalid")?;
+
+    Ok(build_anthropic_agent(client, agent_config))
+}
+
+fn build_anthropic_agent(
+    client: anthropic::Client,
+    AgentConfig { name, preamble }: &AgentConfig,
+) -> Agents {
+    let agent = client
         .agent(CLAUDE_3_7_SONNET)
         .name(name)
         .preamble(preamble)
@@ -70,15 +327,17 @@ fn anthropic_agent(AgentConfig { name, preamble }: &AgentConfig) -> Agents {
 }
 
 /// A function that creates an instance of `Agents` (using the OpenAI variant)
-fn openai_agent(AgentConfig { name, preamble }: &AgentConfig) -> Agents {
-    let agent = openai::Client::from_env()
+fn openai_agent(AgentConfig { name, preamble }: &AgentConfig) -> Result<Agents, ProviderError> {
+    let client: openai::Client = openai::Client::new(api_key("OPENAI_API_KEY")?)?;
+
+    let agent = client
         .completions_api()
         .agent(GPT_4O)
         .name(name)
         .preamble(preamble)
         .build();
 
-    Agents::OpenAI(agent)
+    Ok(Agents::OpenAI(agent))
 }
 
 impl ProviderRegistry {
@@ -86,14 +345,53 @@ impl ProviderRegistry {
     /// This is instantiated with both the Anthropic and OpenAI variants (and their corresponding function pointers)
     pub fn new() -> Self {
         Self(HashMap::from_iter([
-            ("anthropic", anthropic_agent as fn(&AgentConfig) -> Agents),
-            ("openai", openai_agent as fn(&AgentConfig) -> Agents),
+            (RegistryKey::Anthropic, anthropic_agent as AgentFactory),
+            (RegistryKey::OpenAi, openai_agent as AgentFactory),
         ]))
     }
 
+    /// Adds a provider to the registry, replacing any existing factory for the same key.
+    pub fn register(&mut self, provider: RegistryKey, factory: AgentFactory) {
+        self.0.insert(provider, factory);
+    }
+
     /// Attempt to retrieve an Agent.
-    /// If none exists, it will simply return None
-    pub fn agent(&self, provider: &str, agent_config: &AgentConfig) -> Option<Agents> {
-        self.0.get(provider).map(|p| p(agent_config))
+    /// If none exists (or the agent couldn't be created, for example due to a missing API key),
+    /// it will simply return None
+    pub fn agent(&self, provider: RegistryKey, agent_config: &AgentConfig) -> Option<Agents> {
+        self.0.get(&provider).and_then(|p| p(agent_config).ok())
+    }
+
+    /// Tries each provider in order, returning the first one whose agent can be created and passes
+    /// a health check (see `Agents::health_check`).
+    /// Note that each health check is a (small) request to the provider.
+    pub async fn agent_with_fallback(
+        &self,
+        providers: &[&str],
+        agent_config: &AgentConfig<'_>,
+    ) -> Result<(RegistryKey, Agents), FallbackError> {
+        for provider in providers {
+            let key: RegistryKey = provider.parse()?;
+
+            let Some(factory) = self.0.get(&key) else {
+                println!("Provider {key:?} is not registered, skipping");
+                continue;
+            };
+
+            let agent = match factory(agent_config) {
+                Ok(agent) => agent,
+                Err(e) => {
+                    println!("Could not create agent for {key:?}, skipping: {e}");
+                    continue;
+                }
+            };
+
+            match agent.health_check().await {
+                Ok(()) => return Ok((key, agent)),
+                Err(e) => println!("Health check failed for {key:?}, skipping: {e}"),
+            }
+        }
+
+        Err(FallbackError::NoneAvailable)
     }
 }
@@ -0,0 +1,1074 @@
+//! A container type to hold a message history, which can be compacted into a summary
+//! once the conversation gets too long.
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+
+use common::completion::first_text;
+use common::tokens::count_tokens;
+use futures::StreamExt;
+use regex::Regex;
+use rig::agent::{Agent, Text};
+use rig::providers::openai::responses_api::{self, Output, ResponsesCompletionModel};
+use rig::{
+    OneOrMany,
+    completion::{
+        Completion, CompletionError, CompletionModel, CompletionResponse, Message, PromptError,
+    },
+    embeddings::{Embedding, EmbeddingError, EmbeddingModel, distance::VectorDistance},
+    message::{AssistantContent, UserContent},
+    streaming::StreamedAssistantContent,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::RwLock;
+
+/// The model used for chatting (and compaction) in these examples.
+pub const CHAT_MODEL: &str = common::models::openai::CHAT_MODEL;
+
+/// A store for conversation history.
+/// `ConversationMemory` keeps everything in memory, but this could just as easily be implemented
+/// for a persistent backend like Redis or SQLite.
+pub trait ConversationStore {
+    /// Adds any message to the conversation, for example one containing a tool call.
+    fn add_message(&mut self, message: Message);
+
+    /// Returns the messages currently in the conversation.
+    fn get_messages(&self) -> &[Message];
+
+    /// Compacts the conversation by summarizing it with the given model.
+    fn compact<T>(&mut self, model: &T) -> impl Future<Output = Result<(), MemoryError>>
+    where
+        T: CompletionModel;
+}
+
+/// Errors that can occur while managing a conversation's memory.
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryError {
+    /// The model couldn't be prompted, for example because of a network or provider error.
+    #[error(transparent)]
+    Completion(#[from] PromptError),
+    /// The conversation's messages couldn't be embedded.
+    #[error(transparent)]
+    Embedding(#[from] EmbeddingError),
+    /// The model responded with something other than text (like a tool call), so there's nothing to use.
+    #[error("Model returned non-text response")]
+    NonTextResponse,
+    /// A conversation couldn't be read from (or written to) disk.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A recorded conversation (see `load_turns`) wasn't in the expected format.
+    #[error("Invalid conversation file: {0}")]
+    InvalidTurns(#[from] serde_json::Error),
+    /// The conversation has already cost as much as it's allowed to, so no more prompts can be sent.
+    #[error("Budget exceeded: spent ${spent:.4} of a ${limit:.4} limit")]
+    BudgetExceeded { spent: f64, limit: f64 },
+}
+
+impl From<CompletionError> for MemoryError {
+    fn from(e: CompletionError) -> Self {
+        Self::Completion(e.into())
+    }
+}
+
+/// Keeps a running total of a conversation's estimated cost (in US dollars), so that it can be
+/// stopped once it reaches a limit - useful when an agent is left running unattended.
+///
+/// Call `check` before sending each prompt and `record` with the cost of each response
+/// (see `common::cost::estimate_cost`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BudgetGuard {
+    pub spent: f64,
+    pub limit: f64,
+}
+
+impl BudgetGuard {
+    pub fn new(limit: f64) -> Self {
+        Self { spent: 0.0, limit }
+    }
+
+    /// Returns `MemoryError::BudgetExceeded` if the limit has been reached.
+    pub fn check(&self) -> Result<(), MemoryError> {
+        if self.spent >= self.limit {
+            return Err(MemoryError::BudgetExceeded {
+                spent: self.spent,
+                limit: self.limit,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Adds the cost of a turn to the total spent so far.
+    pub fn record(&mut self, cost: f64) {
+        self.spent += cost;
+    }
+
+    /// How much can still be spent before the limit is reached.
+    pub fn remaining(&self) -> f64 {
+        (self.limit - self.spent).max(0.0)
+    }
+}
+
+/// A `ConversationMemory` that can be shared between tasks, for example when several
+/// requests for the same conversation are handled concurrently.
+pub type SharedMemory = Arc<RwLock<ConversationMemory>>;
+
+/// An agent that responds with the whole assistant message, rather than just its text (which is
+/// all `Chat` gives us). Keeping the whole message means keeping its ID.
+pub trait Respond {
+    fn respond(
+        &self,
+        prompt: &str,
+        history: Vec<Message>,
+    ) -> impl Future<Output = Result<Message, PromptError>> + Send;
+}
+
+impl Respond for Agent<ResponsesCompletionModel> {
+    async fn respond(&self, prompt: &str, history: Vec<Message>) -> Result<Message, PromptError> {
+        // `chat` only returns the response text, so we use the lower-level completion API instead
+        let response = self.completion(prompt, history).await?.send().await?;
+
+        Ok(assistant_message(&response))
+    }
+}
+
+/// Builds the assistant message for a response, keeping its ID.
+/// The Responses API uses the ID to link the message back to the response it came from.
+pub fn assistant_message(
+    response: &CompletionResponse<responses_api::CompletionResponse>,
+) -> Message {
+    let id = response
+        .raw_response
+        .output
+        .iter()
+        .find_map(|output| match output {
+            Output::Message(message) => Some(message.id.clone()),
+            _ => None,
+        });
+
+    Message::Assistant {
+        id,
+        content: response.choice.clone(),
+    }
+}
+
+/// Sends a prompt to the agent along with the shared conversation history, then adds the
+/// prompt and response to the history.
+pub async fn chat_with_shared_memory<A: Respond>(
+    agent: &A,
+    prompt: &str,
+    memory: &SharedMemory,
+) -> Result<String, PromptError> {
+    // Copy the history out under a short-lived read lock. The guard is a temporary, so it's
+    // dropped at the end of this statement - before we await the agent. Holding it across that
+    // await would block anyone who wants to write to the memory for the whole LLM call, and
+    // deadlock if the agent itself (or a tool it calls) needs the memory.
+    let history = memory.read().await.get_messages().to_vec();
+
+    let response = agent.respond(prompt, history).await?;
+    let (_, text) = message_text(&response);
+
+    // Only re-acquire the lock (for writing, this time) once we have the response
+    let mut memory = memory.write().await;
+    memory.add_message(Message::user(prompt));
+    memory.add_message(response);
+
+    Ok(text)
+}
+
+/// Who a recorded turn of a conversation came from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A single turn of a recorded conversation.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub text: String,
+}
+
+/// Loads a recorded conversation from a JSON file, which can then be replayed into a
+/// `ConversationMemory` (see `ConversationMemory::replay`) without calling the provider.
+///
+/// The file should contain a list of turns, for example:
+/// `[{"role": "user", "text": "Hello!"}, {"role": "assistant", "text": "Hi there!"}]`
+pub fn load_turns(path: impl AsRef<Path>) -> Result<Vec<Turn>, MemoryError> {
+    let json = std::fs::read_to_string(path)?;
+
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Who sent a message, and the text content of it (other content, like reasoning, is left out).
+fn message_text(message: &Message) -> (Role, String) {
+    match message {
+        Message::User { content } => {
+            let text = content
+                .iter()
+                .filter_map(|x| match x {
+                    UserContent::Text(Text { text }) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            (Role::User, text)
+        }
+        Message::Assistant { content, .. } => {
+            let text = content
+                .iter()
+                .filter_map(|x| match x {
+                    AssistantContent::Text(Text { text }) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            (Role::Assistant, text)
+        }
+    }
+}
+
+/// How similar (by cosine similarity) two messages must be to be clustered together by
+/// `compact_by_clustering`, unless set with `with_clustering_threshold`.
+/// Embeddings from real models tend to score unrelated text fairly highly, so this is set high.
+pub const DEFAULT_CLUSTERING_THRESHOLD: f64 = 0.9;
+
+/// A shared async function that summarizes some text.
+/// This lets `ConversationMemory` hold on to any completion model without needing a type parameter,
+/// and lets forks of a conversation share the same summarizer.
+type SummarizerFn = Arc<
+    dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<String, MemoryError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// When a conversation should be compacted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompactionStrategy {
+    /// Once the conversation has more than this many messages.
+    MessageCount(usize),
+    /// Once the conversation is estimated to use more than this many tokens.
+    TokenBudget(usize),
+    /// Only when `compact` is called.
+    Manual,
+}
+
+pub struct ConversationMemory {
+    messages: Vec<Message>,
+    strategy: CompactionStrategy,
+    summary: Option<String>,
+    /// Follow-up actions extracted from the conversation when it was last compacted
+    action_items: Vec<String>,
+    auto_compact: bool,
+    redact_pii: bool,
+    summarizer: Option<SummarizerFn>,
+    clustering_threshold: f64,
+}
+
+static EMAIL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap());
+static CARD_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:\d[ -]?){12,15}\d\b").unwrap());
+/// Either an international number starting with `+` (eg, `+44 20 7946 0958`), or a separated
+/// 3-3-4 number (eg, `(555) 123-4567`). Other runs of digits, like dates and order numbers,
+/// are left alone.
+static PHONE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}(?:[\s.-]?\d{2,4}){2,4}|\(?\b\d{3}\)?[\s.-]\d{3}[\s.-]\d{4})\b")
+        .unwrap()
+});
+
+/// Replaces emails, credit card numbers and phone numbers with placeholders.
+/// Card numbers are redacted before phone numbers, as the phone pattern would also match them.
+fn redact_pii(text: &str) -> String {
+    let text = EMAIL_REGEX.replace_all(text, "[EMAIL]");
+    let text = CARD_REGEX.replace_all(&text, "[CARD]");
+    let text = PHONE_REGEX.replace_all(&text, "[PHONE]");
+
+    text.into_owned()
+}
+
+impl Default for ConversationMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConversationMemory {
+    pub fn new() -> Self {
+        Self {
+            messages: Vec::new(),
+            strategy: CompactionStrategy::MessageCount(20),
+            summary: None,
+            action_items: Vec::new(),
+            auto_compact: false,
+            redact_pii: false,
+            summarizer: None,
+            clustering_threshold: DEFAULT_CLUSTERING_THRESHOLD,
+        }
+    }
+
+    /// Creates a conversation that should be compacted once it has more than `max_messages` messages.
+    pub fn with_max_messages(max_messages: usize) -> Self {
+        Self::new().with_compaction_strategy(CompactionStrategy::MessageCount(max_messages))
+    }
+
+    /// Sets when the conversation should be compacted.
+    /// A warning is logged once the conversation gets close to its limit.
+    pub fn with_compaction_strategy(mut self, strategy: CompactionStrategy) -> Self {
+        debug_assert!(
+            strategy != CompactionStrategy::MessageCount(0),
+            "max_messages must be greater than 0"
+        );
+        self.strategy = strategy;
+        self
+    }
+
+    /// Enables (or disables) automatic compaction.
+    /// When enabled, messages should be added with `push_message` so that the conversation
+    /// is compacted as soon as its compaction strategy says it should be.
+    pub fn with_auto_compact(mut self, auto_compact: bool) -> Self {
+        self.auto_compact = auto_compact;
+        self
+    }
+
+    /// Uses the given model to summarize the conversation when compacting, instead of the model
+    /// passed to `compact`/`push_message`. This allows a cheaper model to be used for summaries
+    /// than for the conversation itself.
+    pub fn with_summarizer_model<M>(mut self, model: M) -> Self
+    where
+        M: CompletionModel + 'static,
+    {
+        self.summarizer = Some(Arc::new(move |prompt| {
+            let model = model.clone();
+            Box::pin(async move { summarize(&model, &prompt).await })
+        }));
+        self
+    }
+
+    /// Enables (or disables) redaction of emails, phone numbers and card numbers
+    /// from the conversation before it is sent to the model for summarization.
+    pub fn with_pii_redaction(mut self, redact_pii: bool) -> Self {
+        self.redact_pii = redact_pii;
+        self
+    }
+
+    /// Sets how similar two messages must be (by cosine similarity of their embeddings) to be
+    /// clustered together by `compact_by_clustering`. Defaults to `DEFAULT_CLUSTERING_THRESHOLD`.
+    pub fn with_clustering_threshold(mut self, threshold: f64) -> Self {
+        self.clustering_threshold = threshold;
+        self
+    }
+
+    /// Adds a message, compacting the conversation if auto-compaction is enabled and
+    /// `should_compact` returns true.
+    ///
+    /// Unlike `add_user_message`/`add_assistant_message`, this is async and needs a model,
+    /// as compaction makes a call to the LLM to summarize the conversation.
+    pub async fn push_message<T>(&mut self, message: Message, model: &T) -> Result<(), MemoryError>
+    where
+        T: CompletionModel,
+    {
+        self.add_message(message);
+        self.compact_if_needed(model).await
+    }
+
+    /// Adds a user message and the assistant's reply to it, then compacts the conversation if
+    /// auto-compaction is enabled and `should_compact` returns true.
+    ///
+    /// Prefer this to calling `push_message` twice, which could compact the conversation
+    /// between a question and its answer.
+    pub async fn push_turn<T>(
+        &mut self,
+        user: Message,
+        assistant: Message,
+        model: &T,
+    ) -> Result<(), MemoryError>
+    where
+        T: CompletionModel,
+    {
+        self.add_message(user);
+        self.add_message(assistant);
+        self.compact_if_needed(model).await
+    }
+
+    async fn compact_if_needed<T>(&mut self, model: &T) -> Result<(), MemoryError>
+    where
+        T: CompletionModel,
+    {
+        if self.auto_compact && self.should_compact() {
+            println!(
+                "Conversation exceeded its limit ({:?}), compacting...",
+                self.strategy
+            );
+            self.compact(model).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_user_message(&mut self, input: &str) {
+        let message = Message::User {
+            content: OneOrMany::one(UserContent::text(input)),
+        };
+
+        self.add_message(message);
+    }
+
+    pub fn add_assistant_message(&mut self, input: &str) {
+        let message = Message::Assistant {
+            content: OneOrMany::one(AssistantContent::text(input)),
+            id: None,
+        };
+
+        self.add_message(message);
+    }
+
+    /// Adds each of the recorded turns to the conversation, as if they had just happened.
+    /// No calls are made to the model, and the conversation isn't compacted automatically.
+    pub fn replay(&mut self, turns: &[Turn]) {
+        for turn in turns {
+            match turn.role {
+                Role::User => self.add_user_message(&turn.text),
+                Role::Assistant => self.add_assistant_message(&turn.text),
+            }
+        }
+    }
+
+    /// Whether the conversation has outgrown its compaction strategy's limit.
+    /// This is always false for `CompactionStrategy::Manual`.
+    pub fn should_compact(&self) -> bool {
+        match self.strategy {
+            CompactionStrategy::MessageCount(max_messages) => self.messages.len() > max_messages,
+            CompactionStrategy::TokenBudget(token_budget) => self.estimated_tokens() > token_budget,
+            CompactionStrategy::Manual => false,
+        }
+    }
+
+    /// How many more messages can be added before the conversation should be compacted.
+    /// Returns `None` unless the conversation is compacted by message count.
+    pub fn remaining_capacity(&self) -> Option<usize> {
+        match self.strategy {
+            CompactionStrategy::MessageCount(max_messages) => {
+                Some(max_messages.saturating_sub(self.messages.len()))
+            }
+            _ => None,
+        }
+    }
+
+    /// An estimate of how many tokens the conversation uses.
+    pub fn estimated_tokens(&self) -> usize {
+        count_tokens(&self.format_messages_for_summary(), CHAT_MODEL)
+    }
+
+    /// Logs a warning when the conversation is within one message of its message limit (or over it),
+    /// or has used up 90% of its token budget, as a nudge to call `compact`.
+    fn warn_if_near_capacity(&self) {
+        match self.strategy {
+            CompactionStrategy::MessageCount(max_messages) => {
+                let remaining = max_messages.saturating_sub(self.messages.len());
+
+                if remaining <= 1 {
+                    tracing::warn!(
+                        messages = self.messages.len(),
+                        max_messages,
+                        remaining,
+                        "Conversation is nearing its message limit, consider calling `compact`"
+                    );
+                }
+            }
+            CompactionStrategy::TokenBudget(token_budget) => {
+                let tokens = self.estimated_tokens();
+
+                if tokens * 10 >= token_budget * 9 {
+                    tracing::warn!(
+                        tokens,
+                        token_budget,
+                        "Conversation is nearing its token budget, consider calling `compact`"
+                    );
+                }
+            }
+            CompactionStrategy::Manual => {}
+        }
+    }
+
+    /// Creates an independent copy of the conversation (its messages, summary and settings),
+    /// so an alternate continuation can be explored without changing the original.
+    /// The summarizer model, if any, is shared between the two.
+    pub fn fork(&self) -> ConversationMemory {
+        Self {
+            messages: self.messages.clone(),
+            strategy: self.strategy,
+            summary: self.summary.clone(),
+            action_items: self.action_items.clone(),
+            auto_compact: self.auto_compact,
+            redact_pii: self.redact_pii,
+            summarizer: self.summarizer.clone(),
+            clustering_threshold: self.clustering_threshold,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// Renders the conversation as the `messages` array expected by OpenAI's Chat Completions API,
+    /// for use with the raw HTTP endpoint. The summary (if any) is included as a system message.
+    ///
+    /// Only text content is supported - other content (images, tool calls, reasoning, etc.)
+    /// is skipped with a warning.
+    pub fn to_openai_messages(&self) -> serde_json::Value {
+        let mut messages = Vec::new();
+
+        if let Some(summary) = &self.summary {
+            messages.push(json!({
+                "role": "system",
+                "content": format!("Previous conversation summary:\n{summary}"),
+            }));
+        }
+
+        for message in &self.messages {
+            let (role, text) = match message {
+                Message::User { content } => {
+                    let text = content
+                        .iter()
+                        .filter_map(|x| match x {
+                            UserContent::Text(Text { text }) => Some(text.as_str()),
+                            other => {
+                                tracing::warn!("Skipping unsupported user content: {other:?}");
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    ("user", text)
+                }
+                Message::Assistant { content, .. } => {
+                    let text = content
+                        .iter()
+                        .filter_map(|x| match x {
+                            AssistantContent::Text(Text { text }) => Some(text.as_str()),
+                            other => {
+                                tracing::warn!("Skipping unsupported assistant content: {other:?}");
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    ("assistant", text)
+                }
+            };
+
+            messages.push(json!({ "role": role, "content": text }));
+        }
+
+        serde_json::Value::Array(messages)
+    }
+
+    /// Renders the conversation as a readable Markdown transcript, for sharing or debugging.
+    /// The summary (if any) is shown as a blockquote at the top.
+    ///
+    /// Only text content is included - other content (images, tool calls, reasoning, etc.) is skipped.
+    pub fn to_markdown(&self) -> String {
+        let mut sections = Vec::new();
+
+        if let Some(summary) = &self.summary {
+            let quoted = summary
+                .lines()
+                .map(|line| format!("> {line}").trim_end().to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            sections.push(format!(
+                "> **Summary of the earlier conversation:**\n>\n{quoted}"
+            ));
+        }
+
+        for message in &self.messages {
+            let (role, text) = message_text(message);
+            let role = match role {
+                Role::User => "User",
+                Role::Assistant => "Assistant",
+            };
+
+            sections.push(format!("**{role}:**\n\n{text}"));
+        }
+
+        sections.join("\n\n")
+    }
+
+    /// The summary of the conversation so far, if it has been compacted.
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    /// Follow-up actions (for example, "Send the report to Alice") extracted from the conversation
+    /// when it was last compacted.
+    pub fn action_items(&self) -> &[String] {
+        &self.action_items
+    }
+
+    /// Renders the conversation as plain text, as it would be sent to the model for summarization.
+    pub fn format_messages_for_summary(&self) -> String {
+        let formatted = self
+            .messages
+            .iter()
+            .map(|msg| match msg {
+                Message::User { content } => {
+                    let text_content = content
+                        .iter()
+                        .filter_map(|x| {
+                            if let UserContent::Text(Text { text }) = x {
+                                Some(text.to_owned())
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n");
+
+                    format!("User: {text_content}")
+                }
+                Message::Assistant { content, .. } => {
+                    // Reasoning is the model's working rather than its answer, so it shouldn't
+                    // leak into the summary
+                    let text_content = content
+                        .iter()
+                        .filter_map(|x| match x {
+                            AssistantContent::Text(Text { text }) => Some(text.to_owned()),
+                            _ => None,
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n");
+
+                    let has_reasoning = content
+                        .iter()
+                        .any(|x| matches!(x, AssistantContent::Reasoning(_)));
+
+                    if text_content.is_empty() && has_reasoning {
+                        "Assistant: [reasoning omitted]".to_string()
+                    } else {
+                        format!("Assistant: {text_content}")
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if self.redact_pii {
+            redact_pii(&formatted)
+        } else {
+            formatted
+        }
+    }
+}
+
+impl ConversationStore for ConversationMemory {
+    fn add_message(&mut self, message: Message) {
+        self.messages.push(message);
+        self.warn_if_near_capacity();
+    }
+
+    fn get_messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    async fn compact<T>(&mut self, model: &T) -> Result<(), MemoryError>
+    where
+        T: CompletionModel,
+    {
+        if !self.ready_to_compact() {
+            return Ok(());
+        }
+
+        let summary_prompt = self.summary_prompt();
+
+        // Request the summary from the LLM, preferring the dedicated summarizer model if there is one
+        let text = match &self.summarizer {
+            Some(summarizer) => summarizer(summary_prompt).await?,
+            None => summarize(model, &summary_prompt).await?,
+        };
+
+        self.store_summary(&text);
+
+        Ok(())
+    }
+}
+
+impl ConversationMemory {
+    /// The same as `compact`, but the summary is streamed from the model and each chunk of text
+    /// is passed to `on_delta` as it arrives - for example, to show progress while a long
+    /// conversation is being summarized. The full summary is still stored once it's complete.
+    ///
+    /// The summary is always generated by `model`, even if a summarizer model has been set,
+    /// as the summarizer doesn't support streaming.
+    pub async fn compact_streaming<T>(
+        &mut self,
+        model: &T,
+        mut on_delta: impl FnMut(&str),
+    ) -> Result<(), MemoryError>
+    where
+        T: CompletionModel,
+    {
+        if !self.ready_to_compact() {
+            return Ok(());
+        }
+
+        let mut stream = model
+            .completion_request(self.summary_prompt())
+            .stream()
+            .await?;
+
+        let mut text = String::new();
+        while let Some(chunk) = stream.next().await {
+            match chunk? {
+                StreamedAssistantContent::Text(Text { text: delta }) => {
+                    on_delta(&delta);
+                    text.push_str(&delta);
+                }
+                StreamedAssistantContent::ToolCall(_) => return Err(MemoryError::NonTextResponse),
+                _ => {}
+            }
+        }
+
+        // Storing an empty summary would throw away every compacted message,
+        // so a response without any text (for example, reasoning only) is an error
+        if text.trim().is_empty() {
+            return Err(MemoryError::NonTextResponse);
+        }
+
+        self.store_summary(&text);
+
+        Ok(())
+    }
+
+    /// An alternative to `compact` that doesn't need an LLM call. Each turn (a user message along
+    /// with the replies to it) is embedded, then turns that are similar to an earlier turn are
+    /// dropped, leaving one representative (the earliest turn) per cluster. Whole turns are kept or
+    /// dropped together, so a question is never left without its answer (or vice versa).
+    /// This is much cheaper than a summary for long, repetitive histories, but loses whatever
+    /// detail was only in the dropped turns.
+    ///
+    /// Turns without any text (like tool calls) are always kept. No summary is created.
+    pub async fn compact_by_clustering<M: EmbeddingModel>(
+        &mut self,
+        model: &M,
+    ) -> Result<(), MemoryError> {
+        if !self.ready_to_compact() {
+            return Ok(());
+        }
+
+        let turns = self.turn_texts();
+        let texts: Vec<String> = turns
+            .iter()
+            .map(|(_, text)| text.clone())
+            .filter(|text| !text.is_empty())
+            .collect();
+
+        // All turns are embedded in one batch, rather than one request per turn
+        let embeddings = model.embed_texts(texts.clone()).await?;
+        if embeddings.len() != texts.len() {
+            return Err(MemoryError::Embedding(EmbeddingError::ResponseError(
+                format!(
+                    "Expected {} embeddings but the model returned {}",
+                    texts.len(),
+                    embeddings.len()
+                ),
+            )));
+        }
+        let mut embeddings = embeddings.into_iter();
+
+        let mut representatives: Vec<Embedding> = Vec::new();
+        let mut keep = Vec::with_capacity(self.messages.len());
+        for (len, text) in &turns {
+            // Turns without any text weren't embedded, so they're always kept
+            let embedding = if text.is_empty() {
+                None
+            } else {
+                embeddings.next()
+            };
+            let is_duplicate = embedding.is_some_and(|embedding| {
+                let is_duplicate = representatives.iter().any(|rep| {
+                    embedding.cosine_similarity(rep, false) >= self.clustering_threshold
+                });
+                if !is_duplicate {
+                    representatives.push(embedding);
+                }
+                is_duplicate
+            });
+
+            keep.extend(std::iter::repeat_n(!is_duplicate, *len));
+        }
+
+        let before = self.messages.len();
+        let mut keep = keep.into_iter();
+        self.messages.retain(|_| keep.next().unwrap_or(true));
+        tracing::info!(
+            before,
+            after = self.messages.len(),
+            "Compacted conversation by clustering"
+        );
+
+        Ok(())
+    }
+
+    /// Splits the conversation into turns, returning how many messages are in each turn along
+    /// with the turn's text. A turn starts at each user message with text in it, so tool results
+    /// (which are sent as user messages without any text) stay in the turn that made the tool call.
+    fn turn_texts(&self) -> Vec<(usize, String)> {
+        let mut turns: Vec<(usize, String)> = Vec::new();
+
+        for message in &self.messages {
+            let (role, text) = message_text(message);
+
+            match turns.last_mut() {
+                Some((len, turn_text)) if role == Role::Assistant || text.is_empty() => {
+                    *len += 1;
+                    if !text.is_empty() {
+                        turn_text.push('\n');
+                        turn_text.push_str(&text);
+                    }
+                }
+                _ => turns.push((1, text)),
+            }
+        }
+
+        turns
+    }
+
+    /// Whether `compact` should go ahead and summarize the conversation.
+    fn ready_to_compact(&self) -> bool {
+        // There's nothing to summarize, so don't waste a call to the model
+        if self.messages.is_empty() {
+            return false;
+        }
+
+        // With a manual strategy, it's up to the caller to decide when to compact
+        self.strategy == CompactionStrategy::Manual || self.should_compact()
+    }
+
+    /// Creates a prompt asking the LLM to summarize the conversation.
+    /// Action items are asked for separately, so they don't get lost in the prose summary.
+    fn summary_prompt(&self) -> String {
+        format!(
+            "Please provide a concise summary of the following conversation, \
+             capturing key points, decisions, and context. \
+             Also list any follow-up actions that were agreed or requested.\n\
+             Respond with only a JSON object of the form \
+             {{\"summary\": \"...\", \"action_items\": [\"...\"]}}\n\n{}",
+            self.format_messages_for_summary()
+        )
+    }
+
+    /// Replaces the conversation's messages with the summary from the model's response.
+    fn store_summary(&mut self, text: &str) {
+        let CompactionOutput {
+            summary,
+            action_items,
+        } = CompactionOutput::parse(text);
+
+        self.summary = Some(summary);
+        self.action_items = action_items;
+        self.messages.clear();
+    }
+}
+
+/// The structured response we ask for when compacting a conversation.
+#[derive(Deserialize)]
+struct CompactionOutput {
+    summary: String,
+    #[serde(default)]
+    action_items: Vec<String>,
+}
+
+impl CompactionOutput {
+    /// Parses the model's response, which may be wrapped in a Markdown code block.
+    /// If the response isn't valid JSON, the whole response is used as the summary instead.
+    fn parse(text: &str) -> Self {
+        let json = text
+            .trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```")
+            .trim();
+
+        serde_json::from_str(json).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Summary was not valid JSON, storing it as plain text");
+            Self {
+                summary: text.to_string(),
+                action_items: Vec::new(),
+            }
+        })
+    }
+}
+
+/// Asks the model to respond to the given summary prompt, returning the text of its response.
+async fn summarize<M: CompletionModel>(model: &M, prompt: &str) -> Result<String, MemoryError> {
+    let response = model.completion_request(prompt).send().await?;
+
+    // Reasoning models put their reasoning before the text, so skip past it
+    let text = first_text(&response.choice).ok_or(MemoryError::NonTextResponse)?;
+
+    Ok(text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::mock::{MockCompletionModel, MockEmbeddingModel};
+
+    use super::*;
+
+    /// A stand-in for an agent that, while "waiting on the provider", has another task write
+    /// to the shared memory.
+    struct BusyAgent {
+        memory: SharedMemory,
+    }
+
+    impl Respond for BusyAgent {
+        fn respond(
+            &self,
+            _prompt: &str,
+            _history: Vec<Message>,
+        ) -> impl Future<Output = Result<Message, PromptError>> + Send {
+            let memory = self.memory.clone();
+
+            async move {
+                tokio::spawn(async move {
+                    memory
+                        .write()
+                        .await
+                        .add_user_message("Written while the agent was busy");
+                })
+                .await
+                .expect("writer task panicked");
+
+                Ok(Message::Assistant {
+                    id: Some("msg_123".to_string()),
+                    content: OneOrMany::one(AssistantContent::text("Done")),
+                })
+            }
+        }
+    }
+
+    /// If `chat_with_shared_memory` held its read guard while awaiting the agent,
+    /// the agent's write could never complete and this would time out.
+    #[tokio::test]
+    async fn lock_is_not_held_across_await() {
+        let memory: SharedMemory = Arc::new(RwLock::new(ConversationMemory::new()));
+        let agent = BusyAgent {
+            memory: memory.clone(),
+        };
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            chat_with_shared_memory(&agent, "Hello", &memory),
+        )
+        .await;
+
+        assert!(
+            res.is_ok(),
+            "deadlocked: the memory was locked while awaiting the agent"
+        );
+        let memory = memory.read().await;
+        assert_eq!(memory.get_messages().len(), 3);
+        assert!(matches!(
+            memory.get_messages().last(),
+            Some(Message::Assistant { id: Some(id), .. }) if id == "msg_123"
+        ));
+    }
+
+    /// Phone numbers are redacted, but dates and order numbers (which are also runs of digits) aren't.
+    #[test]
+    fn phone_redaction_skips_other_numbers() {
+        let text = redact_pii(
+            "Call +44 20 7946 0958 or (555) 123-4567 about order 12345678 from 2024-01-15.",
+        );
+
+        assert_eq!(
+            text,
+            "Call [PHONE] or [PHONE] about order 12345678 from 2024-01-15."
+        );
+    }
+
+    /// Replaying the recorded fixture should add every turn, in order, without compacting.
+    #[test]
+    fn replays_recorded_conversation() {
+        let turns = load_turns(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/conversation.json"
+        ))
+        .expect("fixture should load");
+
+        let mut memory = ConversationMemory::with_max_messages(3);
+        memory.replay(&turns);
+
+        assert_eq!(memory.get_messages().len(), 10);
+        assert!(matches!(memory.get_messages()[0], Message::User { .. }));
+        assert!(matches!(
+            memory.get_messages()[9],
+            Message::Assistant { .. }
+        ));
+        assert!(memory.summary().is_none());
+    }
+
+    #[tokio::test]
+    async fn clustering_drops_whole_repeated_turns() {
+        let mut memory =
+            ConversationMemory::new().with_compaction_strategy(CompactionStrategy::Manual);
+        memory.add_user_message("When is the deploy?");
+        memory.add_assistant_message("The deploy is scheduled for 3pm today.");
+        memory.add_user_message("Is the build passing?");
+        memory.add_assistant_message("Yes, the build is passing on main.");
+        memory.add_user_message("When is the deploy?");
+        memory.add_assistant_message("The deploy is scheduled for 3pm today.");
+
+        memory
+            .compact_by_clustering(&MockEmbeddingModel::default())
+            .await
+            .expect("mock embeddings don't fail");
+
+        // The repeated turn is dropped as a whole, so questions and answers still alternate
+        let texts: Vec<(Role, String)> = memory.get_messages().iter().map(message_text).collect();
+        assert_eq!(
+            texts,
+            [
+                (Role::User, "When is the deploy?".to_string()),
+                (
+                    Role::Assistant,
+                    "The deploy is scheduled for 3pm today.".to_string()
+                ),
+                (Role::User, "Is the build passing?".to_string()),
+                (
+                    Role::Assistant,
+                    "Yes, the build is passing on main.".to_string()
+                ),
+            ]
+        );
+    }
+
+    /// A summary stream without any text (for example, only reasoning) mustn't replace the
+    /// conversation with an empty summary.
+    #[tokio::test]
+    async fn streaming_compaction_keeps_messages_without_text() {
+        let mut memory =
+            ConversationMemory::new().with_compaction_strategy(CompactionStrategy::Manual);
+        memory.add_user_message("When is the deploy?");
+        memory.add_assistant_message("The deploy is scheduled for 3pm today.");
+
+        let res = memory
+            .compact_streaming(&MockCompletionModel::new(""), |_| {})
+            .await;
+
+        assert!(matches!(res, Err(MemoryError::NonTextResponse)));
+        assert_eq!(memory.get_messages().len(), 2);
+        assert!(memory.summary().is_none());
+    }
+}
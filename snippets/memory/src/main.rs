@@ -3,169 +3,563 @@
 //! Once done, the message history will be compacted, then the summary will be included
 //! with the final LLM prompt.
 //!
+//! A second conversation then shows automatic compaction, where the conversation is compacted
+//! as soon as it grows past the message limit.
+//!
+//! A third conversation is forked after two turns, to show that the fork can diverge from the
+//! original without changing it.
+//!
+//! A final conversation keeps track of its estimated cost, and is stopped once it goes over budget.
+//!
+//! Passing `--replay` instead loads a recorded 10-turn conversation and compacts it, so the only
+//! call made to the model is for the summary. A different recording can be given as a path:
+//! `cargo run -p memory -- --replay path/to/conversation.json`
+//!
 //! The relevant information is printed at each stage to show what is happening.
-use rig::agent::Text;
+use common::cost::estimate_cost;
+use common::health::verify_client_or_exit;
+use common::limits::{WithMaxTokens, max_output_tokens};
+use common::mock::{MockCompletionModel, MockEmbeddingModel};
+use common::models::openai::SMALL_CHAT_MODEL;
+use common::tokens::count_tokens;
+use rig::agent::Agent;
 use rig::client::{CompletionClient, ProviderClient};
-use rig::completion::Prompt;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut mem = ConversationMemory::with_max_messages(2);
+    // Only show warnings, so that we can see when the conversation is nearing its limit
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .init();
+
+    // Fail fast if the API key is invalid, rather than partway through the example.
+    // Try running this with `OPENAI_API_KEY=invalid` to see the error.
+    verify_client_or_exit(&Client::from_env(), SMALL_CHAT_MODEL).await;
+
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("--replay") {
+        let path = args
+            .next()
+            .unwrap_or_else(|| REPLAY_FIXTURE_PATH.to_string());
+        return replay_example(&path).await;
+    }
+
+    // With a limit of 3 messages, a warning will be logged on the second and third message,
+    // and the conversation will be compacted after the fourth
+    let mut mem = ConversationMemory::with_max_messages(3);
 
     let prompt1 = "What is the Rust programming language?";
-    let _ = call_agent_with_chat_history(prompt1, &mut mem.messages).await?;
+    println!("Prompt is {} tokens", count_tokens(prompt1, CHAT_MODEL));
+    let _ = call_agent_with_chat_history(prompt1, &mut mem).await?;
 
     let prompt2 = "Do you think Rust is a good language for AI development? I want to build an AI agent with Rust.";
-    let _ = call_agent_with_chat_history(prompt2, &mut mem.messages).await?;
+    let _ = call_agent_with_chat_history(prompt2, &mut mem).await?;
 
-    println!("Message history: {:?}", mem.messages);
+    // Each assistant message keeps the ID given to it by the Responses API,
+    // which is sent back to OpenAI on the next turn
+    for message in mem.get_messages() {
+        if let Message::Assistant { id, .. } = message {
+            println!("Assistant message ID: {id:?}");
+        }
+    }
 
-    let model = Client::from_env().completion_model("gpt-5.2");
+    println!("Message history: {:?}", mem.get_messages());
+    println!(
+        "Message history (OpenAI format): {}",
+        serde_json::to_string_pretty(&mem.to_openai_messages())?
+    );
+
+    let model = Client::from_env().completion_model(CHAT_MODEL);
 
     println!("Attempting to compact conversation and create a summary...");
     mem.compact(&model).await?;
 
     // SAFETY: We can guarantee that the summary exists here, barring any provider errors
     // which will cause fn main to return early
-    let summary = mem.summary.unwrap();
+    let summary = mem.summary().unwrap().to_string();
     println!("Conversation summary: {summary}");
+    println!("Action items: {:?}", mem.action_items());
 
     let summary_message_plus_prompt =
         format!("Previous conversation summary:\n{summary}\n\nSorry what did we just talk about?",);
 
-    let _ = call_agent_with_chat_history(&summary_message_plus_prompt, &mut mem.messages).await?;
+    let _ = call_agent_with_chat_history(&summary_message_plus_prompt, &mut mem).await?;
+
+    // The conversation (including its summary) can be exported as a Markdown transcript
+    println!("Transcript:\n{}", mem.to_markdown());
+
+    println!("Auto-compaction example\n---");
+    auto_compaction_example(&model).await?;
+
+    println!("Action items example\n---");
+    action_items_example(&model).await?;
+
+    println!("Forking example\n---");
+    forking_example().await?;
+
+    println!("Shared memory example\n---");
+    shared_memory_example().await?;
+
+    println!("PII redaction example\n---");
+    pii_redaction_example();
+
+    println!("Pre-built message example\n---");
+    prebuilt_message_example();
+
+    println!("Reasoning filtering example\n---");
+    reasoning_filtering_example();
+
+    println!("Compaction strategy example\n---");
+    compaction_strategy_example();
+
+    println!("Non-text summary example\n---");
+    non_text_summary_example().await?;
+
+    println!("Empty compaction example\n---");
+    empty_compaction_example().await?;
+
+    println!("Streaming compaction example\n---");
+    streaming_compaction_example(&model).await?;
+
+    println!("Clustering compaction example\n---");
+    clustering_compaction_example().await?;
+
+    println!("Budget guard example\n---");
+    budget_guard_example().await?;
 
     Ok(())
 }
 
-use rig::providers::openai::Client;
-use rig::providers::openai::responses_api::{AdditionalParameters, Reasoning, ReasoningEffort};
-use rig::{
-    OneOrMany,
-    completion::{CompletionModel, Message},
-    message::{AssistantContent, UserContent},
-};
+/// The recorded conversation replayed by `--replay` when no path is given.
+const REPLAY_FIXTURE_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/conversation.json");
+
+/// Replays a recorded conversation, then compacts it. Replaying doesn't call the provider,
+/// so the only LLM call made is for the summary - which makes it easy to see how compaction
+/// behaves on the same conversation every time.
+async fn replay_example(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let turns = load_turns(path)?;
+    let mut mem = ConversationMemory::new().with_compaction_strategy(CompactionStrategy::Manual);
+    mem.replay(&turns);
+    println!(
+        "Replayed {} turns ({} tokens) from {path}",
+        turns.len(),
+        mem.estimated_tokens()
+    );
+
+    let model = Client::from_env().completion_model(CHAT_MODEL);
+    mem.compact(&model).await?;
 
-pub struct ConversationMemory {
-    messages: Vec<Message>,
-    max_messages: usize,
-    summary: Option<String>,
+    println!(
+        "Conversation summary: {}",
+        mem.summary().unwrap_or_default()
+    );
+    println!("Action items:");
+    for item in mem.action_items() {
+        println!("- {item}");
+    }
+
+    Ok(())
 }
 
-impl ConversationMemory {
-    pub fn new() -> Self {
-        Self {
-            messages: Vec::new(),
-            max_messages: 20,
-            summary: None,
-        }
-    }
+/// Compacts a conversation in which the user asks for some follow-ups,
+/// which are pulled out into a list of action items alongside the summary.
+/// The messages are added directly, so the only LLM call made is for compaction.
+async fn action_items_example<T: CompletionModel>(
+    model: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mem = ConversationMemory::with_max_messages(2);
 
-    pub fn with_max_messages(max_messages: usize) -> Self {
-        Self {
-            messages: Vec::new(),
-            max_messages,
-            summary: None,
-        }
+    mem.add_user_message(
+        "Can you remind me to email the quarterly report to Alice on Friday? \
+        Also, I need to book a meeting room for the design review next week.",
+    );
+    mem.add_assistant_message(
+        "Sure! I'll remind you to email the quarterly report to Alice on Friday, \
+        and to book a meeting room for next week's design review.",
+    );
+    mem.add_user_message("Thanks. We also decided to drop support for the legacy API.");
+
+    mem.compact(model).await?;
+
+    println!(
+        "Conversation summary: {}",
+        mem.summary().unwrap_or_default()
+    );
+    println!("Action items:");
+    for item in mem.action_items() {
+        println!("- {item}");
     }
 
-    pub fn add_user_message(&mut self, input: &str) {
-        let message = Message::User {
-            content: OneOrMany::one(UserContent::text(input)),
-        };
+    Ok(())
+}
 
-        self.messages.push(message);
-    }
+/// Forks a conversation after two turns, then continues only the fork with a "what-if" question.
+/// The original conversation is left untouched.
+async fn forking_example() -> Result<(), Box<dyn std::error::Error>> {
+    let mut mem = ConversationMemory::new();
 
-    pub fn add_assistant_message(&mut self, input: &str) {
-        let message = Message::Assistant {
-            content: OneOrMany::one(AssistantContent::text(input)),
-            id: None,
-        };
+    call_agent_with_chat_history("I'm planning a trip to Japan in spring.", &mut mem).await?;
+    call_agent_with_chat_history("Which city should I visit first?", &mut mem).await?;
 
-        self.messages.push(message);
-    }
+    let mut fork = mem.fork();
+    call_agent_with_chat_history("What if I went in winter instead?", &mut fork).await?;
+
+    // The original still only has the first two turns (4 messages), while the fork has a third
+    println!(
+        "Original has {} messages, fork has {} messages",
+        mem.get_messages().len(),
+        fork.get_messages().len()
+    );
+
+    Ok(())
+}
+
+/// Sends two prompts for the same conversation concurrently, sharing one memory between them.
+/// See `chat_with_shared_memory` for how the lock is kept from being held while waiting on the LLM.
+async fn shared_memory_example() -> Result<(), Box<dyn std::error::Error>> {
+    let memory: SharedMemory = Arc::new(RwLock::new(ConversationMemory::new()));
+    let agent = create_agent();
+
+    let (first, second) = tokio::join!(
+        chat_with_shared_memory(&agent, "What is the capital of France?", &memory),
+        chat_with_shared_memory(&agent, "What is the capital of Japan?", &memory),
+    );
+    println!("Assistant: {}", first?);
+    println!("Assistant: {}", second?);
+
+    println!(
+        "Messages in shared memory: {}",
+        memory.read().await.get_messages().len()
+    );
+
+    Ok(())
+}
+
+/// Shows what gets sent to the summarizer when PII redaction is enabled.
+/// No LLM calls are made here.
+fn pii_redaction_example() {
+    let mut mem = ConversationMemory::new().with_pii_redaction(true);
+
+    mem.add_user_message(
+        "Please email me at jane.doe@example.com or call +44 20 7946 0958. \
+        My card number is 4111 1111 1111 1111.",
+    );
+    mem.add_assistant_message("Thanks, I'll be in touch at jane.doe@example.com.");
+
+    println!("Summary input:\n{}", mem.format_messages_for_summary());
+}
+
+/// Adds a pre-built assistant message (with an ID set) directly to the memory,
+/// rather than going through `add_assistant_message`.
+fn prebuilt_message_example() {
+    let mut mem = ConversationMemory::new();
+
+    mem.add_user_message("What is the capital of France?");
+    mem.add_message(Message::assistant_with_id(
+        "msg_123".to_string(),
+        "The capital of France is Paris.",
+    ));
+
+    println!("Message history: {:?}", mem.get_messages());
+}
 
-    pub fn get_messages(&self) -> &[Message] {
-        &self.messages
+/// Shows that reasoning content is left out of what gets sent to the summarizer.
+/// No LLM calls are made here.
+fn reasoning_filtering_example() {
+    let mut mem = ConversationMemory::new();
+
+    mem.add_user_message("What is 12 * 12?");
+    mem.add_message(Message::Assistant {
+        id: None,
+        content: OneOrMany::many(vec![
+            AssistantContent::reasoning("12 * 12 is 12 * 10 + 12 * 2 = 120 + 24 = 144."),
+            AssistantContent::text("12 * 12 is 144."),
+        ])
+        .expect("content is not empty"),
+    });
+    mem.add_user_message("And what is 13 * 13?");
+    // A message that only contains reasoning, for example if the response was cut off
+    mem.add_message(Message::Assistant {
+        id: None,
+        content: OneOrMany::one(AssistantContent::reasoning(
+            "13 * 13 is 13 * 10 + 13 * 3 = 130 + 39 = 169.",
+        )),
+    });
+
+    println!("Summary input:\n{}", mem.format_messages_for_summary());
+}
+
+/// Adds the same messages to conversations with different compaction strategies, showing when
+/// each one decides it should be compacted. No LLM calls are made.
+fn compaction_strategy_example() {
+    let strategies = [
+        CompactionStrategy::MessageCount(2),
+        CompactionStrategy::TokenBudget(40),
+        CompactionStrategy::Manual,
+    ];
+
+    for strategy in strategies {
+        let mut mem = ConversationMemory::new().with_compaction_strategy(strategy);
+
+        mem.add_user_message("What is the Rust programming language?");
+        mem.add_assistant_message("Rust is a systems programming language focused on safety.");
+        println!(
+            "{strategy:?} after 2 short messages ({} tokens): should compact = {}",
+            mem.estimated_tokens(),
+            mem.should_compact()
+        );
+
+        mem.add_user_message(
+            "Can you explain, in as much detail as possible, how the borrow checker decides \
+            whether a reference lives long enough, and what lifetimes have to do with it?",
+        );
+        println!(
+            "{strategy:?} after a long third message ({} tokens): should compact = {}",
+            mem.estimated_tokens(),
+            mem.should_compact()
+        );
     }
+}
 
-    pub fn clear(&mut self) {
-        self.messages.clear();
+/// Compacts a conversation using a (mock) model that responds with a tool call instead of a summary.
+/// Rather than failing, we match on the error and carry on with the conversation uncompacted.
+async fn non_text_summary_example() -> Result<(), Box<dyn std::error::Error>> {
+    let model =
+        MockCompletionModel::with_tool_call("search", serde_json::json!({ "query": "Rust" }));
+    let mut mem = ConversationMemory::with_max_messages(1);
+
+    mem.add_user_message("What is the Rust programming language?");
+    mem.add_assistant_message("Rust is a systems programming language focused on safety.");
+
+    match mem.compact(&model).await {
+        Ok(()) => println!(
+            "Conversation summary: {}",
+            mem.summary().unwrap_or_default()
+        ),
+        Err(MemoryError::NonTextResponse) => println!(
+            "The model didn't return a summary, so the conversation was left as-is ({} messages)",
+            mem.get_messages().len()
+        ),
+        Err(e) => return Err(e.into()),
     }
 
-    pub async fn compact<T>(&mut self, model: &T) -> Result<(), Box<dyn std::error::Error>>
-    where
-        T: CompletionModel,
-    {
-        if self.messages.len() <= self.max_messages {
-            return Ok(());
-        }
+    Ok(())
+}
 
-        // Create a prompt asking the LLM to summarize the conversation
-        let summary_prompt = format!(
-            "Please provide a concise summary of the following conversation, \
-             capturing key points, decisions, and context:\n\n{}",
-            self.format_messages_for_summary()
-        );
+/// Compacts a conversation with no messages in it, which does nothing.
+/// Even with a manual strategy (which always compacts when asked), the model isn't called -
+/// otherwise the mock model's response would have been stored as the summary.
+async fn empty_compaction_example() -> Result<(), Box<dyn std::error::Error>> {
+    let model = MockCompletionModel::default();
+    let mut mem = ConversationMemory::new().with_compaction_strategy(CompactionStrategy::Manual);
 
-        // Request the summary from the LLM
-        let response = model.completion_request(&summary_prompt).send().await?;
+    mem.compact(&model).await?;
+    println!(
+        "Compacted an empty conversation, summary present: {}",
+        mem.summary().is_some()
+    );
 
-        let AssistantContent::Text(Text { text }) = response.choice.first() else {
-            return Err("Model returned non-text response".into());
-        };
+    Ok(())
+}
 
-        self.summary = Some(text);
-        self.messages.clear();
+/// Compacts a conversation while streaming the summary, so it appears as it's generated
+/// rather than all at once when it's finished.
+async fn streaming_compaction_example<T: CompletionModel>(
+    model: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mem = ConversationMemory::new().with_compaction_strategy(CompactionStrategy::Manual);
+
+    mem.add_user_message("What is the Rust programming language?");
+    mem.add_assistant_message(
+        "Rust is a systems programming language focused on safety, speed and concurrency. \
+        Its ownership model guarantees memory safety without needing a garbage collector.",
+    );
+    mem.add_user_message("What is the borrow checker?");
+    mem.add_assistant_message(
+        "The borrow checker is the part of the compiler that enforces Rust's ownership rules: \
+        any number of shared references, or exactly one mutable reference, but never both.",
+    );
+
+    print!("Streaming summary: ");
+    mem.compact_streaming(model, |delta| {
+        print!("{delta}");
+        // stdout is line-buffered, so flush to make sure each delta shows up immediately
+        let _ = std::io::stdout().flush();
+    })
+    .await?;
+    println!();
+
+    println!("Stored summary: {}", mem.summary().unwrap_or_default());
 
-        Ok(())
+    Ok(())
+}
+
+/// Compacts a long, repetitive conversation by clustering similar turns, rather than by asking
+/// an LLM for a summary. Only the first turn of each cluster is kept.
+///
+/// Mock embeddings are used, so no requests are made. As they only capture which words two
+/// messages share, a lower similarity threshold is needed than with a real embedding model.
+async fn clustering_compaction_example() -> Result<(), Box<dyn std::error::Error>> {
+    let turns = [
+        (
+            "Is the build passing?",
+            "Yes, the build is passing on main.",
+        ),
+        (
+            "Is the build still passing?",
+            "Yes, the build is still passing on main.",
+        ),
+        (
+            "When is the deploy?",
+            "The deploy is scheduled for 3pm today.",
+        ),
+        (
+            "Is the build passing now?",
+            "Yes, the build is passing on main now.",
+        ),
+        (
+            "What time is the deploy?",
+            "The deploy is scheduled for 3pm today.",
+        ),
+        (
+            "Who is reviewing my pull request?",
+            "Sam is reviewing your pull request.",
+        ),
+        (
+            "Is the deploy still at 3pm?",
+            "Yes, the deploy is still scheduled for 3pm today.",
+        ),
+        (
+            "Has anyone reviewed my pull request?",
+            "Sam is still reviewing your pull request.",
+        ),
+        (
+            "Is the build passing?",
+            "Yes, the build is passing on main.",
+        ),
+        (
+            "When is the deploy again?",
+            "The deploy is scheduled for 3pm today.",
+        ),
+    ];
+
+    let mut mem = ConversationMemory::new()
+        .with_compaction_strategy(CompactionStrategy::Manual)
+        .with_clustering_threshold(0.6);
+    for (user, assistant) in turns {
+        mem.add_user_message(user);
+        mem.add_assistant_message(assistant);
     }
+    let before = mem.get_messages().len();
+
+    mem.compact_by_clustering(&MockEmbeddingModel::default())
+        .await?;
+    println!(
+        "Compacted {before} messages down to {}:\n{}",
+        mem.get_messages().len(),
+        mem.to_markdown()
+    );
+
+    Ok(())
+}
+
+/// Keeps asking for more detail until the conversation's estimated cost crosses $0.05.
+/// Each turn resends the whole (growing) history, so every turn costs more than the last.
+async fn budget_guard_example() -> Result<(), Box<dyn std::error::Error>> {
+    let mut mem = ConversationMemory::new();
+    let mut budget = BudgetGuard::new(0.05);
+
+    let mut prompt = "Explain the history of the Rust programming language in detail.";
+    // The guard should stop the conversation long before this many turns,
+    // but cap it anyway in case the model gives unusually short answers
+    for _ in 0..20 {
+        match call_agent_with_budget(prompt, &mut mem, &mut budget).await {
+            Ok(_) => println!(
+                "Spent ${:.4} so far, ${:.4} remaining",
+                budget.spent,
+                budget.remaining()
+            ),
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<MemoryError>(),
+                    Some(MemoryError::BudgetExceeded { .. })
+                ) =>
+            {
+                println!("Conversation stopped: {e}");
+                break;
+            }
+            Err(e) => return Err(e),
+        }
 
-    fn format_messages_for_summary(&self) -> String {
-        self.messages
-            .iter()
-            .map(|msg| match msg {
-                Message::User { content } => {
-                    let text_content = content
-                        .iter()
-                        .filter_map(|x| {
-                            if let UserContent::Text(Text { text }) = x {
-                                Some(text.to_owned())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\n");
-
-                    format!("User: {text_content}")
-                }
-                Message::Assistant { content, .. } => {
-                    let text_content = content
-                        .iter()
-                        .filter_map(|x| {
-                            if let AssistantContent::Text(Text { text }) = x {
-                                Some(text.to_owned())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\n");
-
-                    format!("Assistant: {text_content}")
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("\n")
+        prompt = "Go into more detail.";
     }
+
+    Ok(())
 }
 
-async fn call_agent_with_chat_history(
-    prompt: &str,
-    history: &mut Vec<Message>,
-) -> Result<String, Box<dyn std::error::Error>> {
+/// Runs a short conversation with auto-compaction enabled.
+/// The conversation is compacted as soon as it exceeds its message limit, without us calling `compact`.
+///
+/// The conversation itself uses the (more expensive) chat model, but summaries are created
+/// with a cheaper model to save on costs.
+async fn auto_compaction_example<T: CompletionModel>(
+    model: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let summarizer_model = Client::from_env().completion_model(SMALL_CHAT_MODEL);
+    let mut mem = ConversationMemory::with_max_messages(3)
+        .with_auto_compact(true)
+        .with_summarizer_model(summarizer_model);
+    let agent = create_agent();
+
+    let prompts = [
+        "What is the Rust programming language?",
+        "What is the borrow checker?",
+        "What did I ask about first?",
+    ];
+
+    for prompt in prompts {
+        println!("User: {prompt}");
+        let response = agent
+            .completion(prompt, mem.get_messages().to_vec())
+            .await?
+            .send()
+            .await?;
+        println!("Assistant: {}", response_text(&response));
+
+        mem.push_turn(Message::user(prompt), assistant_message(&response), model)
+            .await?;
+
+        println!(
+            "Messages in memory: {}, summary present: {}",
+            mem.get_messages().len(),
+            mem.summary().is_some()
+        );
+    }
+
+    Ok(())
+}
+
+use memory::{
+    BudgetGuard, CHAT_MODEL, CompactionStrategy, ConversationMemory, ConversationStore,
+    MemoryError, SharedMemory, assistant_message, chat_with_shared_memory, load_turns,
+};
+use rig::agent::Text;
+use rig::providers::openai::Client;
+use rig::providers::openai::responses_api::{
+    self, AdditionalParameters, Reasoning, ReasoningEffort, ResponsesCompletionModel,
+};
+use rig::{
+    OneOrMany,
+    completion::{Completion, CompletionModel, CompletionResponse, Message},
+    message::AssistantContent,
+};
+use std::io::Write;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Creates the agent used for chatting in this example.
+fn create_agent() -> Agent<ResponsesCompletionModel> {
     let openai_client = Client::from_env();
 
     // Reasoning with OpenAI requires a verified org which may break this example with GPT-5
@@ -180,17 +574,78 @@ async fn call_agent_with_chat_history(
     }
     .to_json();
 
-    let agent = openai_client
-        .agent("gpt-5.2")
+    openai_client
+        .agent(CHAT_MODEL)
         .preamble("You are a helpful assistant. Be concise.")
         .name("Bob") // used in logging
         .additional_params(additional_params)
-        .build();
+        .with_max_tokens(max_output_tokens())
+        .build()
+}
+
+async fn call_agent_with_chat_history(
+    prompt: &str,
+    memory: &mut impl ConversationStore,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let response = send_turn(prompt, memory).await?;
+
+    Ok(response_text(&response))
+}
+
+/// The same as `call_agent_with_chat_history`, but refuses to send the prompt once the budget has
+/// been used up. The estimated cost of each response is added to the budget.
+async fn call_agent_with_budget(
+    prompt: &str,
+    memory: &mut impl ConversationStore,
+    budget: &mut BudgetGuard,
+) -> Result<String, Box<dyn std::error::Error>> {
+    budget.check()?;
+
+    let response = send_turn(prompt, memory).await?;
+    budget.record(estimate_cost(
+        "openai",
+        CHAT_MODEL,
+        response.usage.input_tokens,
+        response.usage.output_tokens,
+    ));
+
+    Ok(response_text(&response))
+}
+
+/// Sends a prompt along with the conversation history, then adds the prompt and response to the history.
+async fn send_turn(
+    prompt: &str,
+    memory: &mut impl ConversationStore,
+) -> Result<CompletionResponse<responses_api::CompletionResponse>, Box<dyn std::error::Error>> {
+    let agent = create_agent();
 
     println!("User: {prompt}");
 
-    let response_text = agent.prompt(prompt).with_history(history).await?;
-    println!("Assistant: {response_text}");
+    // `chat` only returns the response text, so we use the lower-level completion API instead to
+    // get the full response (including the assistant message's ID)
+    let response = agent
+        .completion(prompt, memory.get_messages().to_vec())
+        .await?
+        .send()
+        .await?;
+
+    println!("Assistant: {}", response_text(&response));
+
+    memory.add_message(Message::user(prompt));
+    memory.add_message(assistant_message(&response));
+
+    Ok(response)
+}
 
-    Ok(response_text)
+/// The text content of a response, with any other content (like reasoning) left out.
+fn response_text(response: &CompletionResponse<responses_api::CompletionResponse>) -> String {
+    response
+        .choice
+        .iter()
+        .filter_map(|content| match content {
+            AssistantContent::Text(Text { text }) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
@@ -0,0 +1,155 @@
+//! A semantic router, which picks a route for a query by comparing the query's embedding
+//! against embeddings of each route's description and examples.
+use common::embeddings::DimensionValidator;
+use common::retry::{retry_embed, retry_search};
+use rig::{
+    OneOrMany,
+    agent::Agent,
+    completion::CompletionModel,
+    embeddings::EmbeddingModel,
+    vector_store::{VectorSearchRequest, VectorStoreIndex, in_memory_store::InMemoryVectorStore},
+};
+use serde::{Deserialize, Serialize};
+
+/// A typed route definition. Has a name, description and examples.
+/// These are all concatenated together when embedded to add more meaning to the embedding.
+#[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RouteDefinition {
+    pub name: String,
+    pub description: String,
+    pub examples: Vec<String>,
+}
+
+/// The routes used by the examples.
+pub fn route_definitions() -> Vec<RouteDefinition> {
+    vec![
+        RouteDefinition {
+            name: "rust".to_string(),
+            description:
+                "Programming, code, and software development in the Rust programming languaage"
+                    .to_string(),
+            examples: vec![
+                "How do I write an async function in Rust?".to_string(),
+                "Debug this code".to_string(),
+                "Implement a sorting algorithm in Rust".to_string(),
+            ],
+        },
+        RouteDefinition {
+            name: "coding".to_string(),
+            description: "General programming and software development questions".to_string(),
+            examples: vec![
+                "How do I write a function?".to_string(),
+                "What does async mean in programming?".to_string(),
+                "Explain this error message".to_string(),
+            ],
+        },
+        RouteDefinition {
+            name: "math".to_string(),
+            description: "Mathematics, calculations, and equations".to_string(),
+            examples: vec![
+                "Solve this equation".to_string(),
+                "Calculate the derivative".to_string(),
+                "What is 15% of 200?".to_string(),
+            ],
+        },
+    ]
+}
+
+/// Creates a semantic router.
+/// Any embedding model can be used here (for example, one created from any client that
+/// implements `EmbeddingsClient`), but the same model must be used when querying the router.
+pub async fn create_semantic_router<M: EmbeddingModel>(
+    embedding_model: &M,
+) -> Result<InMemoryVectorStore<RouteDefinition>, Box<dyn std::error::Error>> {
+    build_semantic_router(route_definitions(), embedding_model).await
+}
+
+/// Creates a semantic router from the agents themselves, using each agent's name as the route name
+/// and its description as the route description. Because the routes are derived from the agents,
+/// they can't drift out of sync with them.
+///
+/// Every agent must have both a name and a description set
+/// (see `AgentBuilder::name` and `AgentBuilder::description`).
+pub async fn create_semantic_router_from_agents<C: CompletionModel, M: EmbeddingModel>(
+    agents: &[&Agent<C>],
+    embedding_model: &M,
+) -> Result<InMemoryVectorStore<RouteDefinition>, Box<dyn std::error::Error>> {
+    let routes = agents
+        .iter()
+        .map(|agent| match (&agent.name, &agent.description) {
+            (Some(name), Some(description)) => Ok(RouteDefinition {
+                name: name.clone(),
+                description: description.clone(),
+                examples: Vec::new(),
+            }),
+            _ => Err(format!(
+                "Agent {:?} needs both a name and a description to be used as a route",
+                agent.name.as_deref().unwrap_or("<unnamed>")
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    build_semantic_router(routes, embedding_model).await
+}
+
+/// Embeds the given routes and adds them to a new vector store.
+async fn build_semantic_router<M: EmbeddingModel>(
+    routes: Vec<RouteDefinition>,
+    embedding_model: &M,
+) -> Result<InMemoryVectorStore<RouteDefinition>, Box<dyn std::error::Error>> {
+    let embedding_texts: Vec<String> = routes
+        .iter()
+        .map(|route| {
+            if route.examples.is_empty() {
+                format!("{}: {}", route.name, route.description)
+            } else {
+                format!(
+                    "{}: {}. Examples: {}",
+                    route.name,
+                    route.description,
+                    route.examples.join(", ")
+                )
+            }
+        })
+        .collect();
+
+    // Embed every route in a single batched request, rather than one request per route.
+    // The request is retried (with backoff) if it fails for a transient reason, like a rate limit.
+    let embeddings =
+        retry_embed(3, || embedding_model.embed_texts(embedding_texts.clone())).await?;
+    DimensionValidator::new().check_all(&embeddings)?;
+
+    let mut vector_store = InMemoryVectorStore::default();
+    vector_store.add_documents(
+        routes
+            .into_iter()
+            .zip(embeddings)
+            .map(|(route, embedding)| (route, OneOrMany::one(embedding))),
+    );
+
+    Ok(vector_store)
+}
+
+/// Routes a given query through a semantic router (see `create_semantic_router`).
+pub async fn semantic_route_query<M: EmbeddingModel + Clone>(
+    query: &str,
+    router: &InMemoryVectorStore<RouteDefinition>,
+    embedding_model: &M,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let index = router.clone().index(embedding_model.clone());
+
+    let req = VectorSearchRequest::builder()
+        .query(query)
+        .samples(1)
+        .build()?;
+
+    // Find most similar route. Embedding the query can be rate limited, so it's retried.
+    let results = retry_search(3, || index.top_n::<RouteDefinition>(req.clone())).await?;
+
+    let route_name = results
+        .first()
+        .map(|(_, _, route_def)| route_def.name.as_str())
+        .unwrap_or("general");
+
+    Ok(route_name.to_string())
+}
@@ -1,110 +1,666 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
+use common::embedding_cache::CachedEmbeddingModel;
+use common::embeddings::DimensionValidator;
+use common::limits::{WithMaxTokens, max_output_tokens};
+use common::mock::{self, FlakyEmbeddingModel, MockCompletionModel, MockEmbeddingModel};
+use common::models::openai::{AGENT_MODEL, EMBED_MODEL, ROUTER_MODEL, SMALL_CHAT_MODEL};
+use common::retry::{retry_embed, retry_search};
+use futures::StreamExt;
+use futures::future::join_all;
+use model_routing::{
+    RouteDefinition, create_semantic_router, create_semantic_router_from_agents, route_definitions,
+    semantic_route_query,
+};
 use rig::{
-    OneOrMany,
-    agent::Agent,
+    agent::{Agent, AgentBuilder, MultiTurnStreamItem, Text},
     client::{CompletionClient, EmbeddingsClient, ProviderClient},
-    completion::Prompt,
+    completion::{CompletionModel, GetTokenUsage, Prompt},
     embeddings::EmbeddingModel,
-    providers::openai::{self, responses_api::ResponsesCompletionModel},
+    providers::openai::{
+        self, TEXT_EMBEDDING_3_LARGE, TEXT_EMBEDDING_3_SMALL,
+        responses_api::ResponsesCompletionModel,
+    },
+    streaming::{StreamedAssistantContent, StreamingPrompt},
     vector_store::{VectorSearchRequest, VectorStoreIndex, in_memory_store::InMemoryVectorStore},
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // With `RIG_MOCK=1`, only the semantic router runs, using a mock embedding model
+    // so that no API key is needed
+    if mock::is_enabled() {
+        let embedding_model = MockEmbeddingModel::default();
+        let semantic_router = create_semantic_router(&embedding_model).await?;
+
+        let prompt = "How do I use async with Rust?";
+        let route_name = semantic_route_query(prompt, &semantic_router, &embedding_model).await?;
+        println!("Route name selected (mock embeddings): {route_name}");
+
+        let agent = AgentBuilder::new(MockCompletionModel::new(format!(
+            "This answer was streamed from the {route_name} route."
+        )))
+        .build();
+        stream_response(&agent, prompt).await?;
+
+        flaky_embedding_example(embedding_model.clone()).await?;
+        cached_embedding_example(embedding_model, "mock", MOCK_EMBEDDING_CACHE_PATH).await?;
+
+        return Ok(());
+    }
+
     println!("Simple LLM-based router impl\n---\n");
     llm_based_router().await?;
     println!("Typed router (with embeddings) impl\n---\n");
 
     let openai_client = openai::Client::from_env();
-    let coding_agent = openai_client
-        .agent("gpt-5")
+    let coding_agent = agent_builder(&openai_client, AGENT_MODEL)
+        .name("rust")
+        .description("Programming, code, and software development in the Rust programming language")
         .preamble("You are an expert coding assistant specializing in Rust programming.")
         .build();
 
-    let math_agent = openai_client
-        .agent("gpt-5")
+    let math_agent = agent_builder(&openai_client, AGENT_MODEL)
+        .name("math")
+        .description("Mathematics, calculations, and equations")
         .preamble("You are a mathematics expert who excels at solving complex problems.")
         .build();
 
-    let rtr = TypedRouter::new()
-        .add_route("rust", coding_agent)
-        .add_route("maths", math_agent);
+    // The routes can also be discovered from the agents' own names and descriptions,
+    // so there's no separate list of route definitions to keep in sync with the agents
+    let embedding_model = openai_client.embedding_model(EMBED_MODEL);
+    let agent_router =
+        create_semantic_router_from_agents(&[&coding_agent, &math_agent], &embedding_model).await?;
+    for query in ["How do I use async with Rust?", "What is 15% of 200?"] {
+        let route_name = semantic_route_query(query, &agent_router, &embedding_model).await?;
+        println!("Route name selected for {query:?} (from agent descriptions): {route_name}");
+    }
+
+    // A cheaper agent for general programming questions
+    let general_coding_agent = agent_builder(&openai_client, SMALL_CHAT_MODEL)
+        .preamble("You are a helpful programming assistant.")
+        .build();
+
+    let mut rtr = TypedRouter::new()
+        .add_route_with_template(
+            "rust",
+            coding_agent,
+            "Answer with idiomatic, compiling Rust code:\n{query}",
+        )
+        .add_route("maths", math_agent)
+        .add_route_with_cost("coding", general_coding_agent, 2.0);
+
+    // Adding a second route with an existing name would silently replace the first one,
+    // so `try_add_route` rejects it instead
+    let duplicate_router = TypedRouter::new()
+        .try_add_route("maths", openai_client.agent(AGENT_MODEL).build())
+        .and_then(|router| {
+            router.try_add_route("maths", openai_client.agent(SMALL_CHAT_MODEL).build())
+        });
+    if let Err(e) = duplicate_router {
+        println!("Failed to add route: {e}");
+    }
+
+    // All routes are embedded in one round-trip, so this takes about as long as a single request
+    let start = Instant::now();
+    let mut semantic_router = create_semantic_router(&embedding_model).await?;
+    println!("Semantic router built in {:?}", start.elapsed());
 
-    let semantic_router = create_semantic_router(&openai_client).await?;
+    flaky_embedding_example(embedding_model.clone()).await?;
+    cached_embedding_example(embedding_model.clone(), EMBED_MODEL, EMBEDDING_CACHE_PATH).await?;
 
     let prompt = "How do I use async with Rust?";
 
-    let route_name = semantic_route_query(prompt, &semantic_router, &openai_client).await?;
+    let route_name = semantic_route_query(prompt, &semantic_router, &embedding_model).await?;
     println!("Route name selected: {route_name}");
 
-    let response = rtr
+    // The rust route wraps queries in its template before they reach the agent,
+    // while the math route (which has no template) passes them through unchanged
+    for (route, query) in [("rust", prompt), ("maths", "What is 15% of 200?")] {
+        println!(
+            "Prompt sent to the {route} route: {}",
+            rtr.apply_template(route, query)
+        );
+    }
+
+    let (route_name, response) = rtr
+        .route_and_prompt(prompt, &semantic_router, &embedding_model)
+        .await?;
+    println!("Response (templated, via {route_name}): {response}");
+
+    // "And how about in Python?" doesn't mention Rust, so on its own it would likely go to the general
+    // "coding" route. Taking the previous route into account keeps the conversation on the rust route.
+    let mut last_route = None;
+    for query in [prompt, "And how about in Python?"] {
+        if let Some((route, _agent)) = rtr
+            .route_sticky(
+                query,
+                last_route.as_ref(),
+                &semantic_router,
+                &embedding_model,
+            )
+            .await?
+        {
+            println!(
+                "Route selected for {query:?}: {} (sticky turns: {})",
+                route.name, route.sticky_turns
+            );
+            last_route = Some(route);
+        }
+    }
+
+    // The semantic router isn't tied to a specific embedding model,
+    // so we can just as easily build one using a newer model
+    let small_embedding_model = openai_client.embedding_model(TEXT_EMBEDDING_3_SMALL);
+    let small_semantic_router = create_semantic_router(&small_embedding_model).await?;
+    let small_route_name =
+        semantic_route_query(prompt, &small_semantic_router, &small_embedding_model).await?;
+    println!("Route name selected (using {TEXT_EMBEDDING_3_SMALL}): {small_route_name}");
+
+    // The full route definition can be used to build an agent on the fly,
+    // using the matched route's description as part of its preamble
+    if let Some((score, route_def)) =
+        semantic_route_match(prompt, &semantic_router, &embedding_model).await?
+    {
+        println!("Route matched: {} (score: {score:.3})", route_def.name);
+
+        let agent = agent_builder(&openai_client, AGENT_MODEL)
+            .preamble(&format!(
+                "You are an expert assistant. You only answer questions about: {}",
+                route_def.description
+            ))
+            .build();
+
+        let response = agent.prompt(prompt).await?;
+        println!("Response: {response}");
+    }
+
+    // Stream the routed agent's response, so the answer starts appearing as soon as the route is picked
+    // rather than after the whole response has been generated
+    println!("Streaming response from the {route_name} route:");
+    let agent = rtr
         .fetch_agent(&route_name)
-        .unwrap()
-        .prompt(prompt)
-        .await
-        .unwrap();
+        .ok_or_else(|| format!("No agent for route {route_name}"))?;
+    stream_response(agent, prompt).await?;
+
+    // The "rust" route is the closest match, but the general "coding" route is still a good match
+    // and is much cheaper - so it wins when we're working with a budget.
+    if let Some((route_name, agent)) = rtr
+        .route_by_budget(prompt, &semantic_router, &embedding_model, 5.0, 0.8)
+        .await?
+    {
+        println!("Route name selected under budget: {route_name}");
+        let response = agent.prompt(prompt).await?;
+        println!("Response: {response}");
+    }
+
+    // This question needs both Rust and maths knowledge, so rather than picking one route
+    // we ask the two closest routes and have a judge combine their answers
+    let ensemble_prompt =
+        "Calculate the derivative of 3x^2 + 2x in Rust, and explain the maths behind it.";
+    let judge = agent_builder(&openai_client, AGENT_MODEL)
+        .preamble("You are a judge who combines answers from several experts into one.")
+        .build();
+    let ensemble = EnsembleRouter::new(&rtr, judge);
+    let response = ensemble
+        .answer(ensemble_prompt, &semantic_router, &embedding_model)
+        .await?;
+    println!("Ensemble response: {response}");
+
+    // This query could reasonably go to either the "rust" or "coding" route.
+    // After each route has been tried, the router sticks with whichever one responded faster.
+    let ambiguous_prompt = "How do I write a function that returns a value?";
+    for _ in 0..3 {
+        let Some((route_name, agent)) = rtr
+            .adaptive_route(ambiguous_prompt, &semantic_router, &embedding_model, 0.05)
+            .await?
+        else {
+            break;
+        };
+        println!("Route name selected adaptively: {route_name}");
+
+        let start = Instant::now();
+        agent.prompt(ambiguous_prompt).await?;
+        let elapsed = start.elapsed();
+        println!("Route {route_name} responded in {elapsed:?}");
+
+        rtr.record_latency(&route_name, elapsed);
+    }
+
+    // If embeddings can't be created (for example, during a provider outage), the router
+    // downgrades itself to LLM-based routing. An invalid API key forces this to happen here.
+    let failing_client: openai::Client = openai::Client::new("invalid-api-key")?;
+    let failing_embedding_model = failing_client.embedding_model(EMBED_MODEL);
+    let router_mode = RouterMode::new(&failing_embedding_model).await;
+
+    let router_agent = agent_builder(&openai_client, ROUTER_MODEL)
+        .preamble("Please return a word from the allowed options list, depending on which word the user's question is more closely related to. Skip all prose.")
+        .build();
+
+    let route_name = router_mode
+        .route(prompt, &failing_embedding_model, &router_agent)
+        .await?;
+    println!("Route name selected (with fallback): {route_name}");
+
+    // Questions about Rust that don't mention Rust by name tend to get routed to the general
+    // "coding" route. Once we've spotted a misrouted query, we can teach the router about it.
+    let misrouted_prompt = "Why does the borrow checker reject my code?";
+    let route_name =
+        semantic_route_query(misrouted_prompt, &semantic_router, &embedding_model).await?;
+    println!("Route name selected before adding example: {route_name}");
+
+    add_route_example(
+        &mut semantic_router,
+        "rust",
+        "How do I satisfy the borrow checker?",
+        &embedding_model,
+    )
+    .await?;
+
+    let route_name =
+        semantic_route_query(misrouted_prompt, &semantic_router, &embedding_model).await?;
+    println!("Route name selected after adding example: {route_name}");
+
+    // The router was built with `EMBED_MODEL` (ada-002, 1536 dimensions), so adding an example
+    // embedded with a different model (3072 dimensions) is rejected rather than silently breaking
+    // the scores
+    let large_embedding_model = openai_client.embedding_model(TEXT_EMBEDDING_3_LARGE);
+    if let Err(e) = add_route_example(
+        &mut semantic_router,
+        "rust",
+        "What is a lifetime?",
+        &large_embedding_model,
+    )
+    .await
+    {
+        println!("Failed to add example: {e}");
+    }
+
+    Ok(())
+}
+
+/// Starts building an agent on the given model, with the output token cap from `RIG_MAX_TOKENS` applied.
+fn agent_builder(client: &openai::Client, model: &str) -> AgentBuilder<ResponsesCompletionModel> {
+    client.agent(model).with_max_tokens(max_output_tokens())
+}
+
+/// Embedding requests can be rate limited just like completions.
+/// Here, the first two requests fail as if the provider had returned a 429, but the router
+/// is still built (and queried) as each request is retried with backoff.
+async fn flaky_embedding_example<M: EmbeddingModel + Clone>(
+    embedding_model: M,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let flaky_embedding_model = FlakyEmbeddingModel::new(embedding_model.clone(), 2);
+    let router = create_semantic_router(&flaky_embedding_model).await?;
+    println!(
+        "Semantic router built despite rate limiting ({} routes)",
+        router.len()
+    );
+
+    // Routing a query embeds it, so that's retried in the same way
+    let flaky_embedding_model = FlakyEmbeddingModel::new(embedding_model, 2);
+    let route_name =
+        semantic_route_query("What is 15% of 200?", &router, &flaky_embedding_model).await?;
+    println!("Route selected despite rate limiting: {route_name}");
+
+    Ok(())
+}
+
+/// Builds the semantic router twice with a cached embedding model.
+/// The second build is served entirely from the cache - as is the first, if this has been run before,
+/// since the cache is saved to disk.
+async fn cached_embedding_example<M: EmbeddingModel>(
+    embedding_model: M,
+    model_name: &str,
+    cache_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cached_embedding_model =
+        CachedEmbeddingModel::load(embedding_model, model_name, cache_path)?;
+
+    for build in 1..=2 {
+        let before = cached_embedding_model.stats();
+        create_semantic_router(&cached_embedding_model).await?;
+        let after = cached_embedding_model.stats();
+
+        println!(
+            "Router build {build}: {} route embeddings served from cache, {} embedded",
+            after.0 - before.0,
+            after.1 - before.1
+        );
+    }
 
-    println!("Response: {response}");
+    cached_embedding_model.save()?;
 
     Ok(())
 }
 
-/// A simple end-to-end example of how you can write an LLM-based router implementation in a single function.
-/// In production, you would probably want to abstract parts of this using the type system
-/// as this is primarily hard-coded to use whatever agents are in this function
+/// A simple end-to-end example of how you can write an LLM-based router implementation.
+/// Each route pairs a name with an agent, and `llm_route` asks a (cheaper) router agent
+/// to pick which route a prompt belongs to before dispatching it.
 pub async fn llm_based_router() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the OpenAI client
     let openai_client = openai::Client::from_env();
 
     // Create specialized agents
-    let coding_agent = openai_client
-        .agent("gpt-5")
-        .preamble("You are an expert coding assistant specializing in Rust programming.")
-        .build();
-
-    let math_agent = openai_client
-        .agent("gpt-5")
-        .preamble("You are a mathematics expert who excels at solving complex problems.")
-        .build();
+    let routes = vec![
+        Route {
+            name: "rust".to_string(),
+            agent: agent_builder(&openai_client, AGENT_MODEL)
+                .preamble("You are an expert coding assistant specializing in Rust programming.")
+                .build(),
+        },
+        Route {
+            name: "maths".to_string(),
+            agent: agent_builder(&openai_client, AGENT_MODEL)
+                .preamble("You are a mathematics expert who excels at solving complex problems.")
+                .build(),
+        },
+        Route {
+            name: "cooking".to_string(),
+            agent: agent_builder(&openai_client, AGENT_MODEL)
+                .preamble("You are a professional chef who gives clear, practical cooking advice.")
+                .build(),
+        },
+    ];
 
-    let router = openai_client
-        .agent("gpt-5-mini") // we can afford to use a less expensive model here as the computation required is significantly less
+    let router = agent_builder(&openai_client, ROUTER_MODEL) // we can afford to use a less expensive model here as the computation required is significantly less
         .preamble(
             "Please return a word from the allowed options list,
-            depending on which word the user's question is more closely related to. Skip all prose.
+            depending on which word the user's question is more closely related to. Skip all prose.",
+        )
+        .build();
 
-            Options: [\'rust', 'maths']
-            ",
+    // For auditability, a second router explains its choice as well as making it
+    let explaining_router = agent_builder(&openai_client, ROUTER_MODEL)
+        .preamble(
+            "Decide which option from the allowed options list the user's question is most closely related to.
+            Respond with only a JSON object of the form {\"route\": \"<option>\", \"reason\": \"<one sentence>\"}.",
         )
         .build();
 
-    let prompt = "How do I use async with Rust?";
-    println!("Prompt: {prompt}");
+    // Route matching is forgiving of the kind of variance you'll see in model output
+    for topic in ["Maths.", " RUST ", "math"] {
+        let route = match_route(&routes, topic)?;
+        println!("Topic {topic:?} matched route: {}", route.name);
+    }
 
-    let topic = router.prompt(prompt).await?;
-    println!("Topic selected: {topic}");
+    // If the explaining router doesn't return valid JSON, we look for a route name in its output instead
+    let (route, reason) = parse_route_decision(&routes, "I'd go with rust for this one")?;
+    println!(
+        "Malformed decision matched route: {} (reason: {reason:?})",
+        route.name
+    );
 
-    let res = if topic.contains("math") {
-        math_agent.prompt(prompt).await?
-    } else if topic.contains("rust") {
-        coding_agent.prompt(prompt).await?
-    } else {
-        return Err(format!("No route found in text: {topic}").into());
-    };
+    // Naming more than one route is ambiguous, so no route is picked
+    if let Err(e) = parse_route_decision(&routes, "Either rust or math would work") {
+        println!("Malformed decision rejected: {e}");
+    }
+
+    for prompt in [
+        "How do I use async with Rust?",
+        "What is the derivative of x^2?",
+        "How long should I boil an egg for?",
+    ] {
+        println!("Prompt: {prompt}");
 
+        let res = llm_route(&router, &routes, prompt).await?;
+
+        println!("Response: {res}");
+    }
+
+    let prompt = "Can you help me make a sourdough starter?";
+    println!("Prompt: {prompt}");
+    let res = llm_route_explained(&explaining_router, &routes, prompt).await?;
     println!("Response: {res}");
 
     Ok(())
 }
 
+/// A named route to an agent, used by `llm_route`.
+struct Route {
+    name: String,
+    agent: OpenAIAgent,
+}
+
+/// Anything with a route name, so that `match_route` can match a router agent's output against
+/// both `Route`s and `RouteDefinition`s.
+trait NamedRoute {
+    fn name(&self) -> &str;
+}
+
+impl NamedRoute for Route {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl NamedRoute for RouteDefinition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Asks the router agent to classify the prompt as one of the given routes,
+/// then sends the prompt to the agent for that route.
+async fn llm_route<M: CompletionModel>(
+    router: &Agent<M>,
+    routes: &[Route],
+    prompt: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let options = routes
+        .iter()
+        .map(|route| format!("'{}'", route.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let topic = router
+        .prompt(format!("Options: [{options}]\n\nQuestion: {prompt}"))
+        .await?;
+    println!("Topic selected: {topic}");
+
+    let route = match_route(routes, &topic)?;
+
+    Ok(route.agent.prompt(prompt).await?)
+}
+
+/// The same as `llm_route`, but the router agent is expected to explain its choice as JSON
+/// (`{"route": "...", "reason": "..."}`), and the reason is printed alongside the route.
+async fn llm_route_explained<M: CompletionModel>(
+    router: &Agent<M>,
+    routes: &[Route],
+    prompt: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let options = routes
+        .iter()
+        .map(|route| format!("'{}'", route.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let output = router
+        .prompt(format!("Options: [{options}]\n\nQuestion: {prompt}"))
+        .await?;
+
+    let (route, reason) = parse_route_decision(routes, &output)?;
+    match reason {
+        Some(reason) => println!("Route selected: {} (reason: {reason})", route.name),
+        None => println!("Route selected: {} (no reason given)", route.name),
+    }
+
+    Ok(route.agent.prompt(prompt).await?)
+}
+
+/// A router agent's decision, along with its justification.
+#[derive(Deserialize)]
+struct RouteDecision {
+    route: String,
+    reason: String,
+}
+
+/// Parses a router agent's JSON decision (which may be wrapped in a Markdown code block),
+/// returning the chosen route and the reason for choosing it.
+/// If the output isn't valid JSON, the route whose name appears in it as a whole word is used
+/// instead, without a reason. It's an error if no route (or more than one route) is named.
+fn parse_route_decision<'a>(
+    routes: &'a [Route],
+    output: &str,
+) -> Result<(&'a Route, Option<String>), Box<dyn std::error::Error>> {
+    let json = output
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+
+    if let Ok(RouteDecision { route, reason }) = serde_json::from_str(json) {
+        return Ok((match_route(routes, &route)?, Some(reason)));
+    }
+
+    // Whole words are matched, so that (for example) "trust" doesn't match the "rust" route
+    let lowercase = output.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let matches: Vec<&Route> = routes
+        .iter()
+        .filter(|route| {
+            words
+                .iter()
+                .any(|word| word_matches_route(word, &route.name))
+        })
+        .collect();
+
+    match matches.as_slice() {
+        [route] => Ok((route, None)),
+        [] => Err(format!("No route found in text: {output}").into()),
+        _ => {
+            let matched = matches
+                .iter()
+                .map(|route| route.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            Err(format!("Ambiguous route in text: {output} (matches {matched})").into())
+        }
+    }
+}
+
+/// Whether a (lowercase) word names the given route.
+/// Singular and plural forms both match, so "math" matches the "maths" route and vice versa.
+fn word_matches_route(word: &str, route_name: &str) -> bool {
+    let route_name = route_name.to_lowercase();
+
+    word == route_name
+        || word.strip_suffix('s') == Some(route_name.as_str())
+        || route_name.strip_suffix('s') == Some(word)
+}
+
+/// Finds the route named by the router agent's output.
+/// Models don't always reply with exactly the word we asked for (eg, "Maths." or " RUST "),
+/// so the output is trimmed, lowercased and stripped of punctuation before matching,
+/// and singular and plural forms both match (see `word_matches_route`).
+fn match_route<'a, R: NamedRoute>(
+    routes: &'a [R],
+    topic: &str,
+) -> Result<&'a R, Box<dyn std::error::Error>> {
+    let normalized = normalize_topic(topic);
+
+    routes
+        .iter()
+        .find(|route| word_matches_route(&normalized, route.name()))
+        .ok_or_else(|| {
+            let valid_routes = routes
+                .iter()
+                .map(|route| route.name())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            format!("No route found in text: {topic} (valid routes: {valid_routes})").into()
+        })
+}
+
+/// Trims, lowercases and strips punctuation from a router agent's output.
+fn normalize_topic(topic: &str) -> String {
+    topic
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_ascii_punctuation())
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Where route embeddings are cached between runs.
+/// Mock embeddings are cached separately, as they can't be mixed with real ones.
+const EMBEDDING_CACHE_PATH: &str = "routing_embedding_cache.json";
+const MOCK_EMBEDDING_CACHE_PATH: &str = "routing_mock_embedding_cache.json";
+
 /// A type alias for an Agent that uses the OpenAI Responses API integration.
 type OpenAIAgent = Agent<ResponsesCompletionModel>;
 
+/// The cost given to routes added without an explicit cost.
+/// This assumes a route uses a full-size model unless told otherwise.
+const DEFAULT_ROUTE_COST: f64 = 10.0;
+
+/// How much weight the latest latency measurement is given in a route's moving average.
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// How much the previous route's score is boosted by, so that an ambiguous follow-up
+/// (like "And how about in Python?") stays on the same route rather than being re-routed from scratch.
+const STICKY_BONUS: f64 = 0.05;
+
+/// How much of the sticky bonus is kept for each turn a route has only been kept because of it.
+/// This stops a conversation from getting stuck on a route once it has drifted away from it.
+const STICKY_DECAY: f64 = 0.5;
+
+/// The route picked for the previous turn of a conversation.
+#[derive(Clone, Debug)]
+struct LastRoute {
+    name: String,
+    /// How many turns in a row the route was kept because of the sticky bonus,
+    /// rather than because it was the closest match.
+    sticky_turns: i32,
+}
+
+impl LastRoute {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            sticky_turns: 0,
+        }
+    }
+
+    /// The bonus given to this route's score, which decays with every sticky turn.
+    fn bonus(&self) -> f64 {
+        STICKY_BONUS * STICKY_DECAY.powi(self.sticky_turns)
+    }
+}
+
+/// An agent registered with a `TypedRouter`, along with the relative cost of using it
+/// and how long it has historically taken to respond.
+struct TypedRoute {
+    agent: OpenAIAgent,
+    cost: f64,
+    avg_latency: Option<Duration>,
+    /// Wraps the user's query before it is sent to the agent. `{query}` is replaced with the query.
+    template: Option<String>,
+}
+
+/// Errors that can occur while configuring a `TypedRouter`.
+#[derive(Debug, thiserror::Error)]
+enum RouterError {
+    /// A route was added with the same name as one that's already registered.
+    #[error("A route named {0:?} already exists")]
+    DuplicateRoute(String),
+}
+
 /// A typed route to hold any `OpenAIAgent` and a string identifier.
 struct TypedRouter {
-    routes: HashMap<String, OpenAIAgent>,
+    routes: HashMap<String, TypedRoute>,
 }
 
 impl TypedRouter {
@@ -114,95 +670,469 @@ impl TypedRouter {
         }
     }
 
-    pub fn add_route(mut self, route_loc: &str, agent: Agent<ResponsesCompletionModel>) -> Self {
-        self.routes.insert(route_loc.to_string(), agent);
+    /// Adds a route with the default cost (see `DEFAULT_ROUTE_COST`).
+    /// If a route with the same name already exists, it's replaced - use `try_add_route`
+    /// to catch this instead.
+    pub fn add_route(self, route_loc: &str, agent: Agent<ResponsesCompletionModel>) -> Self {
+        self.add_route_with_cost(route_loc, agent, DEFAULT_ROUTE_COST)
+    }
+
+    /// The same as `add_route`, but returns `RouterError::DuplicateRoute` rather than replacing
+    /// a route that already has the same name.
+    pub fn try_add_route(
+        self,
+        route_loc: &str,
+        agent: Agent<ResponsesCompletionModel>,
+    ) -> Result<Self, RouterError> {
+        if self.routes.contains_key(route_loc) {
+            return Err(RouterError::DuplicateRoute(route_loc.to_string()));
+        }
+
+        Ok(self.add_route(route_loc, agent))
+    }
+
+    /// Adds a route with a given cost. The cost is relative to other routes -
+    /// for example, it could be the price per million output tokens of the route's model.
+    pub fn add_route_with_cost(
+        mut self,
+        route_loc: &str,
+        agent: Agent<ResponsesCompletionModel>,
+        cost: f64,
+    ) -> Self {
+        self.routes.insert(
+            route_loc.to_string(),
+            TypedRoute {
+                agent,
+                cost,
+                avg_latency: None,
+                template: None,
+            },
+        );
+        self
+    }
+
+    /// Adds a route with the default cost, whose queries are wrapped in the given template
+    /// before being sent to the agent. `{query}` in the template is replaced with the query,
+    /// for example: `"Answer with idiomatic, compiling Rust code:\n{query}"`.
+    pub fn add_route_with_template(
+        mut self,
+        route_loc: &str,
+        agent: Agent<ResponsesCompletionModel>,
+        template: &str,
+    ) -> Self {
+        self.routes.insert(
+            route_loc.to_string(),
+            TypedRoute {
+                agent,
+                cost: DEFAULT_ROUTE_COST,
+                avg_latency: None,
+                template: Some(template.to_string()),
+            },
+        );
         self
     }
 
     pub fn fetch_agent(&self, route: &str) -> Option<&OpenAIAgent> {
-        self.routes.get(route)
+        self.find_route(route).map(|(_, route)| &route.agent)
+    }
+
+    /// Finds a route by name. The semantic router's route names don't always match the names
+    /// agents are registered under (its "math" route goes to the "maths" agent), so singular and
+    /// plural forms of a name both match. Returns the registered name along with the route.
+    fn find_route(&self, name: &str) -> Option<(&str, &TypedRoute)> {
+        if let Some((name, route)) = self.routes.get_key_value(name) {
+            return Some((name.as_str(), route));
+        }
+
+        let name = name.to_lowercase();
+        self.routes
+            .iter()
+            .find(|(route_name, _)| word_matches_route(&name, route_name))
+            .map(|(route_name, route)| (route_name.as_str(), route))
+    }
+
+    /// Wraps the query in the route's template, if it has one.
+    /// Otherwise (or if there's no such route), the query is returned unchanged.
+    pub fn apply_template(&self, route: &str, query: &str) -> String {
+        match self
+            .find_route(route)
+            .and_then(|(_, route)| route.template.as_ref())
+        {
+            Some(template) => template.replace("{query}", query),
+            None => query.to_string(),
+        }
+    }
+
+    /// Picks a route using the semantic router, then prompts the route's agent with the
+    /// (templated) query. Returns the route's name along with the agent's response.
+    pub async fn route_and_prompt<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+    ) -> Result<(String, String), Box<dyn std::error::Error>> {
+        let route_name = semantic_route_query(query, router, embedding_model).await?;
+
+        let (route_name, route) = self
+            .find_route(&route_name)
+            .ok_or_else(|| format!("No agent for route {route_name}"))?;
+
+        let response = route
+            .agent
+            .prompt(self.apply_template(route_name, query))
+            .await?;
+
+        Ok((route_name.to_string(), response))
+    }
+
+    /// Scores every route against the query using the semantic router, best match first.
+    /// Routes are returned under the names their agents are registered with, and routes
+    /// without an agent are left out.
+    async fn score_routes<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+    ) -> Result<Vec<(f64, String)>, Box<dyn std::error::Error>> {
+        let index = router.clone().index(embedding_model.clone());
+
+        let req = VectorSearchRequest::builder()
+            .query(query)
+            .samples(self.routes.len() as u64)
+            .build()?;
+
+        let results = retry_search(3, || index.top_n::<RouteDefinition>(req.clone())).await?;
+
+        // The in-memory vector store doesn't return its results in any particular order
+        let mut scores: Vec<(f64, String)> = results
+            .into_iter()
+            .filter_map(|(score, _, route_def)| {
+                let (name, _) = self.find_route(&route_def.name)?;
+                Some((score, name.to_string()))
+            })
+            .collect();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scores)
+    }
+
+    /// Fetches the agents for the `n` routes closest to the query, best match first,
+    /// along with each route's score.
+    pub async fn fetch_agents<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+        n: usize,
+    ) -> Result<Vec<(f64, String, &OpenAIAgent)>, Box<dyn std::error::Error>> {
+        let results = self.score_routes(query, router, embedding_model).await?;
+
+        Ok(results
+            .into_iter()
+            .filter_map(|(score, name)| {
+                let agent = self.fetch_agent(&name)?;
+                Some((score, name, agent))
+            })
+            .take(n)
+            .collect())
+    }
+
+    /// Picks the cheapest route that is both within budget and semantically similar enough
+    /// to the query (a score of at least `min_score`), rather than simply the closest route.
+    pub async fn route_by_budget<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+        max_cost: f64,
+        min_score: f64,
+    ) -> Result<Option<(String, &OpenAIAgent)>, Box<dyn std::error::Error>> {
+        let results = self.score_routes(query, router, embedding_model).await?;
+
+        let cheapest = results
+            .into_iter()
+            .filter(|(score, _)| *score >= min_score)
+            .filter_map(|(score, name)| {
+                let route = self.routes.get(&name)?;
+                println!(
+                    "Candidate route: {name} (score: {score:.3}, cost: {})",
+                    route.cost
+                );
+                (route.cost <= max_cost).then_some((name, route))
+            })
+            .min_by(|a, b| a.1.cost.total_cmp(&b.1.cost));
+
+        Ok(cheapest.map(|(name, route)| (name, &route.agent)))
+    }
+
+    /// Picks the closest route, unless the previous turn's route scores within its sticky bonus of it -
+    /// in which case the query is ambiguous, so the conversation stays on the previous route.
+    /// Returns the route to pass in as `last_route` on the next turn, along with its agent.
+    pub async fn route_sticky<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        last_route: Option<&LastRoute>,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+    ) -> Result<Option<(LastRoute, &OpenAIAgent)>, Box<dyn std::error::Error>> {
+        let results = self.score_routes(query, router, embedding_model).await?;
+
+        let Some((best_score, best_name)) = results.first() else {
+            return Ok(None);
+        };
+
+        let route = match last_route {
+            Some(last) if last.name == *best_name => LastRoute::new(best_name),
+            Some(last)
+                if results.iter().any(|(score, name)| {
+                    *name == last.name && score + last.bonus() >= *best_score
+                }) =>
+            {
+                LastRoute {
+                    name: last.name.clone(),
+                    sticky_turns: last.sticky_turns + 1,
+                }
+            }
+            _ => LastRoute::new(best_name),
+        };
+
+        Ok(self.fetch_agent(&route.name).map(|agent| (route, agent)))
+    }
+
+    /// Records how long a route took to respond, updating its moving average latency.
+    pub fn record_latency(&mut self, route: &str, duration: Duration) {
+        let Some(route) = self.routes.get_mut(route) else {
+            return;
+        };
+
+        route.avg_latency = Some(match route.avg_latency {
+            // Exponential moving average, so recent calls count for more than older ones
+            Some(avg) => avg.mul_f64(1.0 - LATENCY_SMOOTHING) + duration.mul_f64(LATENCY_SMOOTHING),
+            None => duration,
+        });
+    }
+
+    /// Picks the closest route, unless the runner-up is within `margin` of its score -
+    /// in which case the query is ambiguous, so the historically faster route is picked instead.
+    /// Routes with no recorded latency are preferred, so that every route gets tried at least once.
+    pub async fn adaptive_route<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+        margin: f64,
+    ) -> Result<Option<(String, &OpenAIAgent)>, Box<dyn std::error::Error>> {
+        let results = self.score_routes(query, router, embedding_model).await?;
+
+        let Some((best_score, _)) = results.first() else {
+            return Ok(None);
+        };
+
+        let fastest = results
+            .iter()
+            .filter(|(score, _)| best_score - score <= margin)
+            .filter_map(|(_, name)| Some((name, self.routes.get(name)?)))
+            .min_by_key(|(_, route)| route.avg_latency.unwrap_or_default());
+
+        Ok(fastest.map(|(name, route)| (name.clone(), &route.agent)))
     }
 }
 
-/// A typed route definition. Has a name, description and examples.
-/// These are all concatenated together when embedded to add more meaning to the embedding.
-#[derive(Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
-struct RouteDefinition {
-    name: String,
-    description: String,
-    examples: Vec<String>,
+/// Rather than picking a single route, sends the query to the closest few routes in parallel,
+/// then asks a judge agent to merge their answers.
+/// Each answer is weighted by how closely its route matched the query.
+struct EnsembleRouter<'a> {
+    router: &'a TypedRouter,
+    judge: OpenAIAgent,
+    /// How many routes to query
+    top_k: usize,
 }
 
-/// Creates a semantic router.
-async fn create_semantic_router(
-    openai_client: &openai::Client,
-) -> Result<InMemoryVectorStore<RouteDefinition>, Box<dyn std::error::Error>> {
-    let routes = vec![
-        RouteDefinition {
-            name: "rust".to_string(),
-            description:
-                "Programming, code, and software development in the Rust programming languaage"
-                    .to_string(),
-            examples: vec![
-                "How do I write an async function in Rust?".to_string(),
-                "Debug this code".to_string(),
-                "Implement a sorting algorithm in Rust".to_string(),
-            ],
-        },
-        RouteDefinition {
-            name: "math".to_string(),
-            description: "Mathematics, calculations, and equations".to_string(),
-            examples: vec![
-                "Solve this equation".to_string(),
-                "Calculate the derivative".to_string(),
-                "What is 15% of 200?".to_string(),
-            ],
-        },
-    ];
+impl<'a> EnsembleRouter<'a> {
+    fn new(router: &'a TypedRouter, judge: OpenAIAgent) -> Self {
+        Self {
+            router,
+            judge,
+            top_k: 2,
+        }
+    }
+
+    pub async fn answer<M: EmbeddingModel + Clone>(
+        &self,
+        query: &str,
+        semantic_router: &InMemoryVectorStore<RouteDefinition>,
+        embedding_model: &M,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let agents = self
+            .router
+            .fetch_agents(query, semantic_router, embedding_model, self.top_k)
+            .await?;
 
-    let mut vector_store = InMemoryVectorStore::default();
+        let answers = join_all(
+            agents
+                .iter()
+                .map(|(_, _, agent)| agent.prompt(query).into_future()),
+        )
+        .await;
+
+        // Normalize the scores so the weights add up to 1
+        let total_score: f64 = agents.iter().map(|(score, _, _)| score).sum();
+
+        let mut weighted_answers = String::new();
+        for ((score, name, _), answer) in agents.iter().zip(answers) {
+            let weight = score / total_score;
+            println!("Route {name} answered (weight: {weight:.2})");
+            weighted_answers.push_str(&format!(
+                "Answer from the {name} expert (weight: {weight:.2}):\n{}\n\n",
+                answer?
+            ));
+        }
 
-    for route in routes {
-        let embedding_text = format!(
-            "{}: {}. Examples: {}",
-            route.name,
-            route.description,
-            route.examples.join(", ")
+        let judge_prompt = format!(
+            "Several experts have answered the following question. Merge their answers into a \
+            single answer, giving more weight to experts with a higher weight.\n\n\
+            Question: {query}\n\n{weighted_answers}"
         );
 
-        let embedding = openai_client
-            .embedding_model("text-embedding-ada-002")
-            .embed_text(&embedding_text)
+        Ok(self.judge.prompt(judge_prompt).await?)
+    }
+}
+
+/// How a query gets routed. Semantic routing is preferred, but needs the embedding API to be available.
+enum RouterMode {
+    Semantic(InMemoryVectorStore<RouteDefinition>),
+    /// Asks an agent to pick a route instead of using embeddings.
+    Llm(Vec<RouteDefinition>),
+}
+
+impl RouterMode {
+    /// Builds a semantic router, automatically downgrading to LLM-based routing if the routes
+    /// can't be embedded.
+    async fn new<M: EmbeddingModel>(embedding_model: &M) -> Self {
+        match create_semantic_router(embedding_model).await {
+            Ok(router) => Self::Semantic(router),
+            Err(e) => {
+                println!(
+                    "Failed to create semantic router, falling back to LLM-based routing: {e}"
+                );
+                Self::Llm(route_definitions())
+            }
+        }
+    }
+
+    /// Returns the name of the route the query should be sent to.
+    async fn route<E: EmbeddingModel + Clone, C: CompletionModel>(
+        &self,
+        query: &str,
+        embedding_model: &E,
+        router_agent: &Agent<C>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let routes = match self {
+            Self::Semantic(router) => {
+                return semantic_route_query(query, router, embedding_model).await;
+            }
+            Self::Llm(routes) => routes,
+        };
+
+        let options = routes
+            .iter()
+            .map(|route| format!("'{}' ({})", route.name, route.description))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let topic = router_agent
+            .prompt(format!("Options: [{options}]\n\nQuestion: {query}"))
             .await?;
+        let route = match_route(routes, &topic)?;
+
+        Ok(route.name.clone())
+    }
+}
 
-        vector_store.add_documents(vec![(route, OneOrMany::one(embedding))]);
+/// Streams an agent's response to stdout, printing each text delta as soon as it arrives.
+async fn stream_response<M>(
+    agent: &Agent<M>,
+    prompt: &str,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    M: CompletionModel + 'static,
+    M::StreamingResponse: GetTokenUsage,
+{
+    let mut stream = agent.stream_prompt(prompt).await;
+
+    while let Some(item) = stream.next().await {
+        if let MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(Text {
+            text,
+        })) = item?
+        {
+            print!("{text}");
+            // stdout is line-buffered, so flush to make sure each delta shows up immediately
+            std::io::stdout().flush()?;
+        }
     }
+    println!();
 
-    Ok(vector_store)
+    Ok(())
 }
 
-/// Routes a given query through a semantic router (see `create_semantic_router`).
-async fn semantic_route_query(
+/// Like `semantic_route_query`, but returns the full definition of the closest route along with
+/// its score, rather than just the route's name. Returns `None` if the router has no routes.
+async fn semantic_route_match<M: EmbeddingModel + Clone>(
     query: &str,
     router: &InMemoryVectorStore<RouteDefinition>,
-    openai_client: &openai::Client,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let embedding_model = openai_client.embedding_model("text-embedding-ada-002");
-
-    let index = router.clone().index(embedding_model);
+    embedding_model: &M,
+) -> Result<Option<(f64, RouteDefinition)>, Box<dyn std::error::Error>> {
+    let index = router.clone().index(embedding_model.clone());
 
     let req = VectorSearchRequest::builder()
         .query(query)
         .samples(1)
         .build()?;
 
-    // Find most similar route
-    let results = index.top_n::<RouteDefinition>(req).await.unwrap();
+    let results = retry_search(3, || index.top_n::<RouteDefinition>(req.clone())).await?;
 
-    let route_name = results
-        .first()
-        .map(|(_, _, route_def)| route_def.name.as_str())
-        .unwrap_or("general");
+    Ok(results
+        .into_iter()
+        .next()
+        .map(|(score, _, route_def)| (score, route_def)))
+}
 
-    Ok(route_name.to_string())
+/// Adds an example query to an existing route in a semantic router.
+/// Only the new example is embedded - it's stored as an extra embedding for the route, and a query
+/// matches a route using whichever of the route's embeddings is closest. This means the router can
+/// be improved over time (for example, by adding queries that were previously misrouted) without
+/// having to rebuild it.
+async fn add_route_example<M: EmbeddingModel>(
+    router: &mut InMemoryVectorStore<RouteDefinition>,
+    route_name: &str,
+    example: &str,
+    embedding_model: &M,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((id, (mut route_def, mut embeddings))) = router
+        .iter()
+        .find(|(_, (route_def, _))| route_def.name == route_name)
+        .map(|(id, entry)| (id.clone(), entry.clone()))
+    else {
+        return Err(format!("No route named {route_name}").into());
+    };
+
+    // Every embedding already in the router is checked first, so the new one has something to be
+    // compared against
+    let mut validator = DimensionValidator::new();
+    validator.check_all(
+        router
+            .iter()
+            .flat_map(|(_, (_, embeddings))| embeddings.iter()),
+    )?;
+
+    let embedding = retry_embed(3, || embedding_model.embed_text(example)).await?;
+    validator.check(&embedding)?;
+
+    route_def.examples.push(example.to_string());
+    embeddings.push(embedding);
+
+    // Re-using the route's ID replaces the existing entry
+    router.add_documents_with_ids(vec![(id, route_def, embeddings)]);
+
+    Ok(())
 }
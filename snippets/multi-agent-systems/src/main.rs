@@ -1,17 +1,69 @@
+use common::models::openai::{AGENT_MODEL, SMALL_CHAT_MODEL, TINY_CHAT_MODEL};
+use futures::{
+    FutureExt, StreamExt,
+    future::{BoxFuture, join_all},
+    stream::FuturesUnordered,
+};
 use rig::{
+    agent::Agent,
     client::{CompletionClient, ProviderClient},
-    completion::{Prompt, PromptError},
+    completion::{CompletionModel, Prompt, PromptError, ToolDefinition},
+    tool::Tool,
 };
-use tokio::time::{Duration, interval};
+use serde_json::json;
+use tokio::time::{Duration, Instant, interval};
+use tracing::{Instrument, Span, error, info, info_span, instrument, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The swarm agents log through `tracing`, so install a subscriber to see their output.
+    // Events from `process_autonomous_task` are nested within the `handle_message` span for the same agent.
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
     println!("Manager-worker pattern example\n---");
     manager_worker_agent().await?;
 
+    println!("Manager-worker with typed arguments example\n---");
+    typed_manager_worker_agent().await?;
+
+    println!("Parallel workers example\n---");
+    parallel_workers(3).await?;
+
+    println!("Concurrency limit example\n---");
+    concurrency_limit_example().await?;
+
+    println!("Consensus example\n---");
+    consensus_example().await?;
+
     println!("Swarm agent example\n---");
     swarm_agent_example().await?;
 
+    println!("Task timeout example\n---");
+    task_timeout_example().await?;
+
+    println!("Broadcast policy example\n---");
+    broadcast_policy_example().await?;
+
+    println!("History persistence example\n---");
+    history_persistence_example().await?;
+
+    println!("Task cancellation example\n---");
+    task_cancellation_example().await?;
+
+    println!("Failed task example\n---");
+    failed_task_example().await?;
+
+    println!("Rate limiting example\n---");
+    rate_limit_example().await?;
+
+    println!("Trace propagation example\n---");
+    trace_propagation_example().await?;
+
+    println!("Backpressure example\n---");
+    backpressure_example().await?;
+
     Ok(())
 }
 
@@ -23,14 +75,14 @@ async fn manager_worker_agent() -> Result<(), Box<dyn std::error::Error>> {
     let prompt = "Ask Bob to write an email for you and let me know what he has written.";
     println!("Prompt: {prompt}");
 
-    let bob = openai_client.agent("gpt-5")
+    let bob = openai_client.agent(AGENT_MODEL)
         .name("Bob")
         .description("An employee who works in admin at FooBar Inc.")
         .preamble("You are Bob, an employee working in admin at FooBar Inc. Alice, your manager, may ask you to do things. You need to do them.")
         .build();
 
     let alice = openai_client
-        .agent("gpt-5")
+        .agent(AGENT_MODEL)
         .name("Alice")
         .description("A manager at FooBar Inc.")
         .preamble("You are a manager in the admin department at FooBar Inc. You manage Bob.")
@@ -46,21 +98,435 @@ async fn manager_worker_agent() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// The instructions a manager passes to the email writer.
+#[derive(Debug, Deserialize)]
+struct EmailRequest {
+    recipient: String,
+    subject: String,
+    key_points: Vec<String>,
+}
+
+/// Wraps an agent as a tool that takes typed arguments.
+/// Adding an agent directly with `.tool(agent)` gives the tool a single free-text `prompt` argument,
+/// whereas here the manager has to fill in each field of an `EmailRequest`.
+struct EmailWriter<M: CompletionModel> {
+    agent: Agent<M>,
+}
+
+impl<M: CompletionModel + 'static> Tool for EmailWriter<M> {
+    const NAME: &'static str = "write_email";
+    type Error = PromptError;
+    type Args = EmailRequest;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Ask Bob, an employee in admin, to write an email".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "recipient": {
+                        "type": "string",
+                        "description": "Who the email is to"
+                    },
+                    "subject": {
+                        "type": "string",
+                        "description": "The subject line of the email"
+                    },
+                    "key_points": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "The points the email must cover"
+                    }
+                },
+                "required": ["recipient", "subject", "key_points"],
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        println!("[tool-call] Asking Bob to write an email: {args:?}");
+
+        // The structured arguments are turned back into a prompt for the sub-agent
+        let prompt = format!(
+            "Write an email to {} with the subject \"{}\". It must cover the following points:\n- {}",
+            args.recipient,
+            args.subject,
+            args.key_points.join("\n- ")
+        );
+
+        self.agent.prompt(prompt).await
+    }
+}
+
+/// The same as `manager_worker_agent`, but Bob is given to Alice as a tool with typed arguments,
+/// so Alice passes structured instructions (recipient, subject and key points) rather than free text.
+async fn typed_manager_worker_agent() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = rig::providers::openai::Client::from_env();
+
+    let bob = openai_client.agent(AGENT_MODEL)
+        .name("Bob")
+        .preamble("You are Bob, an employee working in admin at FooBar Inc. You write emails when asked to.")
+        .build();
+
+    let alice = openai_client
+        .agent(AGENT_MODEL)
+        .name("Alice")
+        .description("A manager at FooBar Inc.")
+        .preamble("You are a manager in the admin department at FooBar Inc. You manage Bob.")
+        .tool(EmailWriter { agent: bob })
+        .build();
+
+    let prompt = "Ask Bob to email the facilities team about the broken air conditioning on the \
+        third floor. Mention that it has been broken since Monday and that it needs fixing before \
+        the client visit on Thursday. Then let me know what he has written.";
+    println!("Prompt: {prompt}");
+
+    // Allow an extra turn so Alice can respond after receiving Bob's email
+    let res = alice.prompt(prompt).multi_turn(2).await?;
+
+    println!("Response: {res}");
+
+    Ok(())
+}
+
+/// An example of a manager fanning the same subtask out to several workers at once, then aggregating their answers.
+/// Because none of the workers depend on each other, the total latency is roughly that of the slowest worker rather than the sum of all of them.
+/// At most `max_concurrent` workers are prompted at once.
+async fn parallel_workers(max_concurrent: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = rig::providers::openai::Client::from_env();
+
+    let subtask =
+        "Suggest a name for a new coffee shop. Reply with the name and one sentence explaining it.";
+    println!("Subtask: {subtask}");
+
+    let workers = ["Bob", "Carol", "Dave"].map(|name| {
+        openai_client
+            .agent(AGENT_MODEL)
+            .name(name)
+            .preamble(&format!(
+                "You are {name}, an employee working in marketing at FooBar Inc. Your manager may ask you to do things. You need to do them."
+            ))
+            .build()
+    });
+
+    // Send the subtask to every worker concurrently
+    let now = Instant::now();
+    let jobs: Vec<_> = workers.iter().map(|worker| (worker, subtask)).collect();
+    let responses = prompt_with_concurrency_limit(&jobs, max_concurrent).await;
+    let elapsed = now.elapsed();
+
+    let mut answers = Vec::new();
+    for (worker, response) in workers.iter().zip(responses) {
+        let answer = response?;
+        let name = worker.name.as_deref().unwrap_or("Unknown");
+        println!("[{name}] {answer}");
+        answers.push(format!("{name}: {answer}"));
+    }
+
+    // For comparison, send the same subtask to each worker one after another
+    let now = Instant::now();
+    for worker in &workers {
+        worker.prompt(subtask).await?;
+    }
+    let sequential_elapsed = now.elapsed();
+
+    println!(
+        "All workers finished in {}ms concurrently, compared to {}ms sequentially",
+        elapsed.as_millis(),
+        sequential_elapsed.as_millis()
+    );
+
+    let manager = openai_client
+        .agent(AGENT_MODEL)
+        .name("Alice")
+        .preamble("You are a manager in the marketing department at FooBar Inc. Your employees have sent you their suggestions. Pick the best one and explain your choice in one sentence.")
+        .build();
+
+    let res = manager.prompt(answers.join("\n")).await?;
+    println!("Aggregated result: {res}");
+
+    Ok(())
+}
+
+/// Prompts each worker with its subtask concurrently, with at most `max_concurrent` prompts in flight at once.
+/// The rest wait for a permit, so a manager with many subtasks doesn't overwhelm the provider.
+/// Responses are returned in the same order as `jobs`.
+async fn prompt_with_concurrency_limit<W: Prompt>(
+    jobs: &[(&W, &str)],
+    max_concurrent: usize,
+) -> Vec<Result<String, PromptError>> {
+    let permits = Semaphore::new(max_concurrent.max(1));
+    let start = Instant::now();
+
+    join_all(jobs.iter().enumerate().map(|(idx, (worker, subtask))| {
+        let permits = &permits;
+        async move {
+            let _permit = permits
+                .acquire()
+                .await
+                .expect("concurrency limit semaphore is never closed");
+            println!(
+                "[Subtask {idx}] started after {}ms",
+                start.elapsed().as_millis()
+            );
+
+            let response = worker.prompt(*subtask).await;
+            println!(
+                "[Subtask {idx}] finished after {}ms",
+                start.elapsed().as_millis()
+            );
+            response
+        }
+    }))
+    .await
+}
+
+/// An example of a manager dispatching five subtasks to a worker, with only two running at a time.
+/// The logged start times show the subtasks running in batches: each one only starts once an earlier one has finished.
+async fn concurrency_limit_example() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = rig::providers::openai::Client::from_env();
+
+    let worker = openai_client
+        .agent(SMALL_CHAT_MODEL)
+        .name("Bob")
+        .preamble("You are Bob, an employee working in marketing at FooBar Inc. Your manager may ask you to do things. You need to do them.")
+        .build();
+
+    let subtasks = [
+        "Suggest a name for a new coffee shop. Reply with just the name.",
+        "Suggest a slogan for a new coffee shop. Reply with just the slogan.",
+        "Suggest a signature drink for a new coffee shop. Reply with just the drink.",
+        "Suggest an opening day promotion for a new coffee shop. Reply in one sentence.",
+        "Suggest a colour scheme for a new coffee shop. Reply in one sentence.",
+    ];
+    let jobs: Vec<_> = subtasks.iter().map(|subtask| (&worker, *subtask)).collect();
+
+    let responses = prompt_with_concurrency_limit(&jobs, 2).await;
+
+    for (subtask, response) in subtasks.iter().zip(responses) {
+        println!("{subtask}\n-> {}", response?);
+    }
+
+    Ok(())
+}
+
+/// Sends the same question to every worker concurrently, then has the manager pick (or synthesize) the answer most of the workers agree on.
+async fn consensus_answer<M: Prompt, W: Prompt>(
+    manager: &M,
+    workers: &[W],
+    question: &str,
+) -> Result<String, PromptError> {
+    let responses = join_all(
+        workers
+            .iter()
+            .map(|worker| worker.prompt(question).into_future()),
+    )
+    .await;
+
+    let mut answers = Vec::new();
+    for (idx, response) in responses.into_iter().enumerate() {
+        let answer = response?;
+        println!("[Worker {idx}] {answer}");
+        answers.push(format!("Worker {idx}: {answer}"));
+    }
+
+    let aggregation_prompt = format!(
+        "Question: {question}\n\nAnswers:\n{}\n\nThe answers above may disagree. Reply with the answer that the majority agree on, or if there is no majority, the answer you believe is most accurate.",
+        answers.join("\n")
+    );
+
+    manager.prompt(aggregation_prompt).await
+}
+
+/// An example of three workers (each using a different model) answering a factual question, with a manager resolving any disagreement.
+async fn consensus_example() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = rig::providers::openai::Client::from_env();
+
+    let question =
+        "In what year was the first version of the Rust programming language (1.0) released?";
+    println!("Question: {question}");
+
+    let workers = [AGENT_MODEL, SMALL_CHAT_MODEL, TINY_CHAT_MODEL].map(|model| {
+        openai_client
+            .agent(model)
+            .preamble("Answer the question in one short sentence.")
+            .build()
+    });
+
+    let manager = openai_client
+        .agent(AGENT_MODEL)
+        .name("Alice")
+        .preamble("You are a manager who is responsible for resolving disagreements between your workers.")
+        .build();
+
+    let res = consensus_answer(&manager, &workers, question).await?;
+    println!("Consensus: {res}");
+
+    Ok(())
+}
+
 use rig::providers::openai;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tokio::sync::mpsc;
+use tokio::sync::{RwLock, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
 /// Message types for inter-agent communication
 #[derive(Debug, Clone)]
 enum AgentMessage {
-    Task(String),
-    Response(String, String), // (from_agent_id, content)
+    Task(String, String, Option<mpsc::Sender<AgentMessage>>), // (task_id, content, reply_to)
+    Cancel(String),                                           // task_id
+    Response(String, String),                                 // (from_agent_id, content)
+    Result(String, TaskResult, Span), // (from_agent_id, result, span the result was sent from)
+    Busy(String, String),             // (from_agent_id, rejected task_id)
     Trigger(String),
     Shutdown,
 }
 
+/// Errors that can occur while an agent is processing a task
+#[derive(Debug, thiserror::Error)]
+enum TaskError {
+    #[error("task timed out after {0:?}")]
+    TaskTimeout(Duration),
+    #[error("task was cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Prompt(#[from] PromptError),
+}
+
+/// How a task finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskStatus {
+    Completed,
+    Failed,
+    TimedOut,
+}
+
+/// The outcome of a task, broadcast to peers once the task has finished.
+/// For a task that didn't complete, `output` holds the error instead.
+#[derive(Debug, Clone)]
+struct TaskResult {
+    task_id: String,
+    status: TaskStatus,
+    output: String,
+    duration: Duration,
+}
+
+/// Returned by an agent once it has shut down cleanly, along with its final metrics.
+/// If an agent is aborted (or panics) instead, its join handle returns an error rather than this.
+#[derive(Debug)]
+struct ShutdownAck {
+    agent_id: String,
+    tasks_completed: usize,
+    /// Tasks that failed or timed out
+    tasks_failed: usize,
+    tasks_cancelled: usize,
+    /// Tasks that were still in flight when the agent shut down, and so were abandoned
+    tasks_abandoned: usize,
+    /// Tasks that were turned away because the agent already had too many in flight
+    tasks_rejected: usize,
+}
+
+impl std::fmt::Display for ShutdownAck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Agent {} shut down cleanly, completed {} tasks.",
+            self.agent_id, self.tasks_completed
+        )?;
+
+        if self.tasks_failed > 0 {
+            write!(f, " {} tasks failed.", self.tasks_failed)?;
+        }
+
+        if self.tasks_cancelled > 0 {
+            write!(f, " {} tasks were cancelled.", self.tasks_cancelled)?;
+        }
+
+        if self.tasks_abandoned > 0 {
+            write!(
+                f,
+                " {} in-flight tasks were abandoned.",
+                self.tasks_abandoned
+            )?;
+        }
+
+        if self.tasks_rejected > 0 {
+            write!(
+                f,
+                " {} tasks were rejected while busy.",
+                self.tasks_rejected
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What an agent should do when a peer's inbox is full while broadcasting
+#[derive(Debug, Clone, Copy)]
+enum BroadcastPolicy {
+    /// Wait until the peer has room. One slow peer will stall the sender.
+    Block,
+    /// Skip the peer and log a warning
+    DropOnFull,
+    /// Retry up to N times with a short delay between attempts, then skip the peer
+    RetryN(usize),
+}
+
+/// Limits how often provider calls can be made, shared between agents.
+/// This is a token bucket: each call takes a token from the bucket, and tokens are added back
+/// at a fixed rate up to the bucket's capacity. Bursts of up to `capacity` calls are allowed,
+/// but over time calls can't be made faster than the refill rate.
+#[derive(Clone)]
+struct RateLimiter {
+    tokens: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket of `capacity` tokens, with one token added back every `refill_every`.
+    fn new(capacity: usize, refill_every: Duration) -> Self {
+        let tokens = Arc::new(Semaphore::new(capacity));
+
+        // The refill task only holds a weak reference, so it stops once the limiter is dropped
+        let bucket = Arc::downgrade(&tokens);
+        tokio::spawn(async move {
+            let mut ticker = interval(refill_every);
+            // The first tick completes straight away, and the bucket starts full anyway
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                let Some(tokens) = bucket.upgrade() else {
+                    break;
+                };
+                if tokens.available_permits() < capacity {
+                    tokens.add_permits(1);
+                }
+            }
+        });
+
+        Self { tokens }
+    }
+
+    /// Waits until a token is available, then takes it out of the bucket.
+    async fn acquire(&self) {
+        self.tokens
+            .acquire()
+            .await
+            .expect("rate limiter semaphore is never closed")
+            .forget();
+    }
+}
+
 /// Agent state
+#[derive(Default, Serialize, Deserialize)]
 struct AgentState {
     task_queue: Vec<String>,
     conversation_history: Vec<String>,
@@ -73,15 +539,26 @@ struct AutonomousAgent {
     state: Arc<RwLock<AgentState>>,
     inbox: mpsc::Receiver<AgentMessage>,
     peer_channels: Arc<RwLock<Vec<mpsc::Sender<AgentMessage>>>>,
+    /// How long a single task may take before it's abandoned
+    task_timeout: Duration,
+    broadcast_policy: BroadcastPolicy,
+    /// Where the agent's state is saved to on shutdown, if anywhere
+    history_path: Option<PathBuf>,
+    /// Shared with other agents to limit calls to the provider, if set
+    rate_limiter: Option<RateLimiter>,
+    /// How many tasks the agent will work on at once before rejecting new ones, if limited
+    max_in_flight: Option<usize>,
 }
 
 impl AutonomousAgent {
-    fn new(id: String, api_key: String, inbox: mpsc::Receiver<AgentMessage>) -> Self {
+    fn new(
+        id: String,
+        api_key: String,
+        inbox: mpsc::Receiver<AgentMessage>,
+        task_timeout: Duration,
+    ) -> Self {
         let client = openai::Client::new(&api_key).unwrap();
-        let state = Arc::new(RwLock::new(AgentState {
-            task_queue: Vec::new(),
-            conversation_history: Vec::new(),
-        }));
+        let state = Arc::new(RwLock::new(AgentState::default()));
 
         Self {
             id,
@@ -89,7 +566,75 @@ impl AutonomousAgent {
             state,
             inbox,
             peer_channels: Arc::new(RwLock::new(Vec::new())),
+            task_timeout,
+            broadcast_policy: BroadcastPolicy::DropOnFull,
+            history_path: None,
+            rate_limiter: None,
+            max_in_flight: None,
+        }
+    }
+
+    /// Persist the agent's conversation history to a JSON file on shutdown.
+    /// If the file already exists (ie, from a previous run), the history is reloaded from it.
+    fn with_history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        if let Ok(contents) = std::fs::read_to_string(&path) {
+            match serde_json::from_str::<AgentState>(&contents) {
+                Ok(state) => {
+                    info!(
+                        agent_id = %self.id,
+                        entries = state.conversation_history.len(),
+                        path = %path.display(),
+                        "Restored conversation history"
+                    );
+                    self.state = Arc::new(RwLock::new(state));
+                }
+                Err(e) => {
+                    warn!(agent_id = %self.id, error = %e, "Ignoring unreadable history file")
+                }
+            }
         }
+
+        self.history_path = Some(path);
+        self
+    }
+
+    /// Write the agent's state to its history file (if it has one)
+    async fn save_history(&self) {
+        let Some(path) = &self.history_path else {
+            return;
+        };
+
+        let state = self.state.read().await;
+        let res = serde_json::to_string_pretty(&*state)
+            .map_err(std::io::Error::other)
+            .and_then(|json| std::fs::write(path, json));
+
+        if let Err(e) = res {
+            error!(agent_id = %self.id, error = %e, "Failed to save history");
+        }
+    }
+
+    /// Set what happens when a peer's inbox is full while broadcasting (defaults to `BroadcastPolicy::DropOnFull`)
+    fn with_broadcast_policy(mut self, broadcast_policy: BroadcastPolicy) -> Self {
+        self.broadcast_policy = broadcast_policy;
+        self
+    }
+
+    /// Share a rate limiter with this agent. A token is taken from it before each call to the provider.
+    fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Limit how many tasks the agent works on at once. Any task received while the agent is at
+    /// its limit is rejected, and its sender is replied to with `AgentMessage::Busy` (if the task
+    /// came with a reply channel) so they know to try elsewhere.
+    /// Without a limit, the agent accepts every task it's sent, however many it's already working on.
+    fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
     }
 
     /// Register peer agents for communication
@@ -98,124 +643,356 @@ impl AutonomousAgent {
         peers.push(peer_channel);
     }
 
-    /// Send message to all peer agents
+    /// Send message to all peer agents, following the agent's broadcast policy if a peer's inbox is full
     async fn broadcast_to_peers(&self, message: AgentMessage) {
         let peers = self.peer_channels.read().await;
-        for peer in peers.iter() {
-            let _ = peer.send(message.clone()).await;
+        for (idx, peer) in peers.iter().enumerate() {
+            if let BroadcastPolicy::Block = self.broadcast_policy {
+                if peer.send(message.clone()).await.is_err() {
+                    warn!(agent_id = %self.id, peer = idx, "Peer has shut down, skipping");
+                }
+                continue;
+            }
+
+            let max_retries = match self.broadcast_policy {
+                BroadcastPolicy::RetryN(n) => n,
+                _ => 0,
+            };
+
+            let mut attempt = 0;
+            loop {
+                match peer.try_send(message.clone()) {
+                    Ok(()) => break,
+                    Err(mpsc::error::TrySendError::Full(_)) if attempt < max_retries => {
+                        attempt += 1;
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        warn!(agent_id = %self.id, peer = idx, "Peer inbox is full, dropping message");
+                        break;
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        warn!(agent_id = %self.id, peer = idx, "Peer has shut down, skipping");
+                        break;
+                    }
+                }
+            }
         }
     }
 
     /// Process autonomous task using LLM
     /// This currently shows a simple LLM prompt, but if you wanted you could give your agent some tools!
-    async fn process_autonomous_task(&self, task: &str) -> Result<String, PromptError> {
+    /// If the provider takes longer than the agent's task timeout to respond, or the task is cancelled
+    /// using the given token, the task is abandoned.
+    /// Time spent waiting for the rate limiter doesn't count towards the timeout.
+    #[instrument(skip(self, cancel), fields(agent_id = %self.id))]
+    async fn process_autonomous_task(
+        &self,
+        task: &str,
+        cancel: CancellationToken,
+    ) -> Result<String, TaskError> {
         let agent = self
             .client
-            .agent("gpt-5")
+            .agent(AGENT_MODEL)
             .preamble(&format!(
                 "Your name is {}. Process tasks autonomously and coordinate with other agents.",
                 self.id
             ))
             .build();
 
-        let response = agent.prompt(task).await?;
+        // Wait for a rate limit token before calling the provider
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let start = Instant::now();
+            tokio::select! {
+                _ = cancel.cancelled() => return Err(TaskError::Cancelled),
+                _ = rate_limiter.acquire() => {}
+            }
+            info!(waited = ?start.elapsed(), "Acquired rate limit token");
+        }
+
+        let response = tokio::select! {
+            _ = cancel.cancelled() => return Err(TaskError::Cancelled),
+            res = tokio::time::timeout(self.task_timeout, agent.prompt(task).into_future()) => {
+                res.map_err(|_| TaskError::TaskTimeout(self.task_timeout))??
+            }
+        };
+
+        info!(result_len = response.len(), "Task processed");
         Ok(response)
     }
 
+    /// Processes a task, storing the result and broadcasting it to peers (whether it succeeded or not).
+    /// Cancelled tasks aren't broadcast, as whoever cancelled them already knows.
+    /// Returns the task's ID once it has finished, along with how it finished (`None` if it was cancelled).
+    #[instrument(skip(self, task, cancel), fields(agent_id = %self.id))]
+    async fn handle_task(
+        &self,
+        task_id: String,
+        task: String,
+        cancel: CancellationToken,
+    ) -> (String, Option<TaskStatus>) {
+        info!(%task, "Received task");
+        let start = Instant::now();
+
+        let (status, output) = match self.process_autonomous_task(&task, cancel).await {
+            Ok(result) => {
+                info!(%task, result_len = result.len(), %result, "Completed task");
+                (TaskStatus::Completed, result)
+            }
+            Err(TaskError::TaskTimeout(timeout)) => {
+                warn!(%task, ?timeout, "Abandoned task, ready for the next one");
+                (TaskStatus::TimedOut, format!("Timed out after {timeout:?}"))
+            }
+            Err(TaskError::Cancelled) => {
+                info!(%task, "Task cancelled");
+                return (task_id, None);
+            }
+            Err(e) => {
+                error!(%task, error = %e, "Error processing task");
+                (TaskStatus::Failed, e.to_string())
+            }
+        };
+
+        let result = TaskResult {
+            task_id: task_id.clone(),
+            status,
+            output,
+            duration: start.elapsed(),
+        };
+
+        // Store in history
+        {
+            let mut state = self.state.write().await;
+            state.conversation_history.push(format!(
+                "Task: {} | Status: {:?} | Result: {}",
+                task, result.status, result.output
+            ));
+        }
+
+        // Broadcast result to peers, along with the current span so that peers can
+        // handle it as part of the same trace
+        self.broadcast_to_peers(AgentMessage::Result(
+            self.id.clone(),
+            result,
+            Span::current(),
+        ))
+        .await;
+
+        (task_id, Some(status))
+    }
+
+    /// Records a task result from the peer `from_id`, logging it according to how the task went.
+    async fn handle_result(&self, from_id: &str, result: TaskResult) {
+        let TaskResult {
+            task_id,
+            status,
+            output,
+            duration,
+        } = &result;
+
+        // Peers can react differently depending on how the task went
+        match status {
+            TaskStatus::Completed => {
+                info!(%task_id, ?duration, %output, "Peer completed task")
+            }
+            TaskStatus::Failed => {
+                error!(%task_id, ?duration, error = %output, "Peer task failed")
+            }
+            TaskStatus::TimedOut => {
+                warn!(%task_id, ?duration, "Peer task timed out")
+            }
+        }
+
+        let mut state = self.state.write().await;
+        state.conversation_history.push(format!(
+            "Task {} from {} {:?} after {:?} | Result: {}",
+            task_id, from_id, status, duration, output
+        ));
+    }
+
+    #[instrument(skip(self), fields(agent_id = %self.id))]
     async fn handle_message(&self, task: AgentMessage) {
         match task {
-            AgentMessage::Task(task) => {
-                println!("[{}] Received task: {}", self.id, task);
-
-                match self.process_autonomous_task(&task).await {
-                    Ok(result) => {
-                        println!("[{}] Completed task: {}", self.id, result);
-
-                        // Store in history
-                        let mut state = self.state.write().await;
-                        state
-                            .conversation_history
-                            .push(format!("Task: {} | Result: {}", task, result));
-
-                        // Broadcast result to peers
-                        self.broadcast_to_peers(AgentMessage::Response(self.id.clone(), result))
-                            .await;
-                    }
-                    Err(e) => eprintln!("[{}] Error processing task: {}", self.id, e),
-                }
-            }
             AgentMessage::Response(from_id, content) => {
-                println!(
-                    "[{}] Received response from {}: {}",
-                    self.id, from_id, content
-                );
+                info!(from = %from_id, %content, "Received response");
                 let mut state = self.state.write().await;
                 state
                     .conversation_history
                     .push(format!("From {}: {}", from_id, content));
             }
+            AgentMessage::Result(from_id, result, sender_span) => {
+                // Handle the result in a child of the sender's span, so a task flowing through
+                // several agents shows up as one connected trace.
+                // It's also linked to this agent's own span, so it can be found from either side.
+                let span = info_span!(
+                    parent: &sender_span,
+                    "handle_result",
+                    agent_id = %self.id,
+                    from = %from_id
+                );
+                span.follows_from(Span::current());
+
+                self.handle_result(&from_id, result).instrument(span).await;
+            }
+            AgentMessage::Busy(from_id, task_id) => {
+                warn!(from = %from_id, %task_id, "Peer was busy and rejected a task");
+            }
             AgentMessage::Trigger(trigger_msg) => {
-                println!("[{}] External trigger: {}", self.id, trigger_msg);
+                info!(trigger = %trigger_msg, "External trigger");
                 // Process trigger autonomously
-                let _ = self.process_autonomous_task(&trigger_msg).await;
+                let _ = self
+                    .process_autonomous_task(&trigger_msg, CancellationToken::new())
+                    .await;
             }
             message => {
-                println!("Unsupported message variant received: {message:?}");
+                warn!(?message, "Unsupported message variant received");
                 // this could theoretically return an error or panic
                 // this should never return the shutdown enum variant because enums are eagerly evaluated
             }
         }
     }
 
-    // Main actor loop
-    async fn run(mut self) {
-        println!("Agent '{}' started and running autonomously", self.id);
+    /// Main actor loop. Runs until the agent receives `AgentMessage::Shutdown`, then acknowledges it.
+    async fn run(mut self) -> ShutdownAck {
+        info!(agent_id = %self.id, "Agent started and running autonomously");
 
         // External trigger: periodic self-check (runs every 10 seconds)
         let mut tick_interval = interval(Duration::from_secs(10));
 
+        // Take the inbox out of the agent, so that in-flight tasks can borrow the agent
+        // while we carry on receiving messages (for example, to cancel one of them)
+        let mut inbox = std::mem::replace(&mut self.inbox, mpsc::channel(1).1);
+        let mut in_flight = FuturesUnordered::new();
+        // Other work (handling peer messages and the periodic tick) runs here rather than in the
+        // `select!` arms, so that a slow LLM call doesn't stop the agent from noticing finished
+        // tasks, cancellations or shutdown in the meantime
+        let mut background: FuturesUnordered<BoxFuture<'_, ()>> = FuturesUnordered::new();
+        let mut cancel_tokens: HashMap<String, CancellationToken> = HashMap::new();
+        let mut tasks_completed = 0;
+        let mut tasks_failed = 0;
+        let mut tasks_cancelled = 0;
+        let mut tasks_rejected = 0;
+
         loop {
             tokio::select! {
                 // Handle incoming messages from other agents
-                Some(msg) = self.inbox.recv() => {
+                Some(msg) = inbox.recv() => {
                     match msg {
                         AgentMessage::Shutdown => {
-                            println!("Shutting down...");
+                            info!(agent_id = %self.id, "Shutting down");
+                            self.save_history().await;
                             break
                         }
+                        AgentMessage::Task(task_id, _, reply_to)
+                            if self.max_in_flight.is_some_and(|max| in_flight.len() >= max) =>
+                        {
+                            warn!(agent_id = %self.id, %task_id, in_flight = in_flight.len(), "At capacity, rejecting task");
+                            tasks_rejected += 1;
+                            // Only the sender needs to know, so reply to it rather than broadcasting
+                            if let Some(reply_to) = reply_to
+                                && reply_to.try_send(AgentMessage::Busy(self.id.clone(), task_id)).is_err()
+                            {
+                                warn!(agent_id = %self.id, "Could not reply to the sender of a rejected task");
+                            }
+                        }
+                        AgentMessage::Task(task_id, task, _) => {
+                            let cancel = CancellationToken::new();
+                            cancel_tokens.insert(task_id.clone(), cancel.clone());
+                            in_flight.push(self.handle_task(task_id, task, cancel));
+                        }
+                        AgentMessage::Cancel(task_id) => {
+                            match cancel_tokens.remove(&task_id) {
+                                Some(cancel) => cancel.cancel(),
+                                None => warn!(agent_id = %self.id, %task_id, "No in-flight task to cancel"),
+                            }
+                        }
                         _ => {
-                            self.handle_message(msg).await;
+                            background.push(self.handle_message(msg).boxed());
                         }
                     }
                 }
+                // Clean up after tasks that have finished
+                Some((task_id, status)) = in_flight.next(), if !in_flight.is_empty() => {
+                    cancel_tokens.remove(&task_id);
+                    match status {
+                        Some(TaskStatus::Completed) => tasks_completed += 1,
+                        Some(TaskStatus::Failed | TaskStatus::TimedOut) => tasks_failed += 1,
+                        None => tasks_cancelled += 1,
+                    }
+                }
+                Some(()) = background.next(), if !background.is_empty() => {}
                 // Autonomous periodic task (external trigger)
                 _ = tick_interval.tick() => {
-                    println!("[{}] Autonomous tick - checking for self-initiated tasks", self.id);
-
-                    // Check if agent should create its own task
-                    // Use scoped brackets here to avoid needing to manually drop lock
-                    let needs_to_create_own_task =  {
-                        let state_rlock = self.state.read().await;
-                        state_rlock.task_queue.is_empty() && !state_rlock.conversation_history.is_empty()
-                    };
-
-                    // Check if agent should create its own task
-                    if needs_to_create_own_task {
-                        let summary_task = "Summarize what you've accomplished so far in one sentence.";
-                        match self.process_autonomous_task(summary_task).await {
-                            Ok(summary) => {
-                                println!("[{}] Self-initiated summary: {}", self.id, summary);
+                    info!(agent_id = %self.id, "Autonomous tick - checking for self-initiated tasks");
+
+                    let agent = &self;
+                    background.push(async move {
+                        // Check if agent should create its own task
+                        // Use scoped brackets here to avoid needing to manually drop lock
+                        let needs_to_create_own_task =  {
+                            let state_rlock = agent.state.read().await;
+                            state_rlock.task_queue.is_empty() && !state_rlock.conversation_history.is_empty()
+                        };
+
+                        // Check if agent should create its own task
+                        if needs_to_create_own_task {
+                            let summary_task = "Summarize what you've accomplished so far in one sentence.";
+                            match agent.process_autonomous_task(summary_task, CancellationToken::new()).await {
+                                Ok(summary) => {
+                                    info!(agent_id = %agent.id, %summary, "Self-initiated summary");
+                                }
+                                Err(e) => error!(agent_id = %agent.id, error = %e, "Error in autonomous task"),
                             }
-                            Err(e) => eprintln!("[{}] Error in autonomous task: {}", self.id, e),
                         }
-                    }
+                    }.boxed());
                 }
             }
         }
+
+        ShutdownAck {
+            agent_id: self.id.clone(),
+            tasks_completed,
+            tasks_failed,
+            tasks_cancelled,
+            tasks_abandoned: in_flight.len(),
+            tasks_rejected,
+        }
     }
 }
 
+/// How long the swarm example runs for before shutting its agents down, unless `SWARM_RUNTIME_SECS` is set.
+const DEFAULT_SWARM_RUNTIME: Duration = Duration::from_secs(30);
+
+/// How long the swarm's agents get to shut down once their runtime is up, before they're aborted.
+const SWARM_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Runs the swarm for `SWARM_RUNTIME_SECS` seconds (30 by default), for example:
+/// `SWARM_RUNTIME_SECS=5 cargo run -p multi-agent-systems`
+///
+/// The whole run is capped at the runtime plus a grace period, so an agent that gets stuck
+/// can't hang the process.
 async fn swarm_agent_example() -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = match std::env::var("SWARM_RUNTIME_SECS") {
+        Ok(secs) => Duration::from_secs(secs.parse()?),
+        Err(_) => DEFAULT_SWARM_RUNTIME,
+    };
+    let ceiling = runtime + SWARM_SHUTDOWN_GRACE;
+
+    // If the ceiling is hit, the swarm future is dropped, which aborts any agents still running
+    match tokio::time::timeout(ceiling, run_swarm(runtime)).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!(
+                ?ceiling,
+                "Swarm did not shut down in time, aborting its agents"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Runs three agents for the given amount of time, then shuts them down.
+async fn run_swarm(runtime: Duration) -> Result<(), Box<dyn std::error::Error>> {
     let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
 
     // Create channels for agent communication
@@ -224,9 +1001,13 @@ async fn swarm_agent_example() -> Result<(), Box<dyn std::error::Error>> {
     let (tx3, rx3) = mpsc::channel(100);
 
     // Create agents
-    let agent1 = AutonomousAgent::new("Tom".to_string(), api_key.clone(), rx1);
-    let agent2 = AutonomousAgent::new("Richard".to_string(), api_key.clone(), rx2);
-    let agent3 = AutonomousAgent::new("Harry".to_string(), api_key, rx3);
+    let task_timeout = Duration::from_secs(60);
+    // Each agent uses a different policy for when a peer's inbox is full
+    let agent1 = AutonomousAgent::new("Tom".to_string(), api_key.clone(), rx1, task_timeout);
+    let agent2 = AutonomousAgent::new("Richard".to_string(), api_key.clone(), rx2, task_timeout)
+        .with_broadcast_policy(BroadcastPolicy::RetryN(3));
+    let agent3 = AutonomousAgent::new("Harry".to_string(), api_key, rx3, task_timeout)
+        .with_broadcast_policy(BroadcastPolicy::Block);
 
     // Register peers (each agent knows about the others)
     agent1.register_peer(tx2.clone()).await;
@@ -236,35 +1017,333 @@ async fn swarm_agent_example() -> Result<(), Box<dyn std::error::Error>> {
     agent3.register_peer(tx1.clone()).await;
     agent3.register_peer(tx2.clone()).await;
 
-    // Spawn agent actors
-    let handle1 = tokio::spawn(agent1.run());
-    let handle2 = tokio::spawn(agent2.run());
-    let handle3 = tokio::spawn(agent3.run());
+    // Spawn agent actors.
+    // Unlike `tokio::spawn`, a `JoinSet` aborts its tasks when dropped.
+    let mut agents = JoinSet::new();
+    agents.spawn(agent1.run());
+    agents.spawn(agent2.run());
+    agents.spawn(agent3.run());
 
     // Send initial task to Agent-Alpha
     tx1.send(AgentMessage::Task(
+        "analysis".to_string(),
         "Analyze the benefits of autonomous agent systems".to_string(),
+        None,
     ))
     .await?;
 
     // External trigger example
-    tokio::time::sleep(Duration::from_secs(5)).await;
+    let trigger_delay = runtime.min(Duration::from_secs(5));
+    tokio::time::sleep(trigger_delay).await;
     tx2.send(AgentMessage::Trigger(
         "Check system status and report findings".to_string(),
     ))
     .await?;
 
     // Let agents run for demonstration
-    tokio::time::sleep(Duration::from_secs(30)).await;
+    tokio::time::sleep(runtime.saturating_sub(trigger_delay)).await;
 
     // Shutdown
     tx1.send(AgentMessage::Shutdown).await?;
     tx2.send(AgentMessage::Shutdown).await?;
     tx3.send(AgentMessage::Shutdown).await?;
 
+    // Each agent acknowledges a clean shutdown with its final metrics
+    while let Some(res) = agents.join_next().await {
+        let ack = res?;
+        println!("{ack}");
+    }
+
+    Ok(())
+}
+
+/// An example of an agent with a very short task timeout abandoning a long-running task, then carrying on handling messages.
+async fn task_timeout_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (tx, rx) = mpsc::channel(100);
+    let agent = AutonomousAgent::new("Impatient".to_string(), api_key, rx, Duration::from_secs(2));
+    let handle = tokio::spawn(agent.run());
+
+    // This will take far longer than 2 seconds to answer, so the agent gives up on it
+    tx.send(AgentMessage::Task(
+        "essay".to_string(),
+        "Write a detailed 2000 word essay on the history of distributed systems".to_string(),
+        None,
+    ))
+    .await?;
+
+    // The agent is still running and can handle the next message
+    tx.send(AgentMessage::Response(
+        "Tom".to_string(),
+        "Are you still there?".to_string(),
+    ))
+    .await?;
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    tx.send(AgentMessage::Shutdown).await?;
+    handle.await?;
+
+    Ok(())
+}
+
+/// An example of an agent broadcasting to a peer that isn't reading its inbox.
+/// With `BroadcastPolicy::DropOnFull`, messages that don't fit are dropped rather than stalling the sender.
+async fn broadcast_policy_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (_tx, rx) = mpsc::channel(100);
+    let agent = AutonomousAgent::new("Tom".to_string(), api_key, rx, Duration::from_secs(60))
+        .with_broadcast_policy(BroadcastPolicy::DropOnFull);
+
+    // A flooded peer that can only hold 2 messages and never reads them
+    let (flooded_tx, mut flooded_rx) = mpsc::channel(2);
+    agent.register_peer(flooded_tx).await;
+
+    for i in 0..5 {
+        agent
+            .broadcast_to_peers(AgentMessage::Response(
+                agent.id.clone(),
+                format!("Update #{i}"),
+            ))
+            .await;
+    }
+
+    // Only the first 2 messages made it through
+    flooded_rx.close();
+    while let Some(msg) = flooded_rx.recv().await {
+        println!("Flooded peer received: {msg:?}");
+    }
+
+    Ok(())
+}
+
+/// An example of an agent's conversation history surviving a shutdown/restart cycle.
+async fn history_persistence_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    let history_path = "Tom_history.json";
+
+    // Start from a clean slate so the example is repeatable
+    let _ = std::fs::remove_file(history_path);
+
+    let (tx, rx) = mpsc::channel(100);
+    let agent = AutonomousAgent::new(
+        "Tom".to_string(),
+        api_key.clone(),
+        rx,
+        Duration::from_secs(60),
+    )
+    .with_history_file(history_path);
+    let handle = tokio::spawn(agent.run());
+
+    tx.send(AgentMessage::Response(
+        "Richard".to_string(),
+        "The quarterly report is finished.".to_string(),
+    ))
+    .await?;
+    tx.send(AgentMessage::Shutdown).await?;
+    handle.await?;
+
+    // Restart the agent using the same history file
+    let (_tx, rx) = mpsc::channel(100);
+    let agent = AutonomousAgent::new("Tom".to_string(), api_key, rx, Duration::from_secs(60))
+        .with_history_file(history_path);
+
+    for entry in agent.state.read().await.conversation_history.iter() {
+        println!("[{}] Remembered: {}", agent.id, entry);
+    }
+
+    Ok(())
+}
+
+/// An example of cancelling a single in-flight task, while the agent keeps working on its other tasks.
+async fn task_cancellation_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (tx, rx) = mpsc::channel(100);
+    let agent = AutonomousAgent::new("Tom".to_string(), api_key, rx, Duration::from_secs(60));
+    let handle = tokio::spawn(agent.run());
+
+    tx.send(AgentMessage::Task(
+        "essay".to_string(),
+        "Write a detailed 2000 word essay on the history of distributed systems".to_string(),
+        None,
+    ))
+    .await?;
+    tx.send(AgentMessage::Task(
+        "sum".to_string(),
+        "What is 2 + 2? Reply with just the number.".to_string(),
+        None,
+    ))
+    .await?;
+
+    // We've changed our mind about the essay - the sum will still be answered
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    tx.send(AgentMessage::Cancel("essay".to_string())).await?;
+
+    tokio::time::sleep(Duration::from_secs(20)).await;
+    tx.send(AgentMessage::Shutdown).await?;
+    handle.await?;
+
+    Ok(())
+}
+
+/// An example of a task failing (here, because of an invalid API key).
+/// The failure is broadcast to the agent's peer with a `Failed` status, rather than only being logged locally.
+async fn failed_task_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (tx1, rx1) = mpsc::channel(100);
+    let (tx2, rx2) = mpsc::channel(100);
+    let broken = AutonomousAgent::new(
+        "Broken".to_string(),
+        "invalid".to_string(),
+        rx1,
+        Duration::from_secs(60),
+    );
+    let observer = AutonomousAgent::new("Tom".to_string(), api_key, rx2, Duration::from_secs(60));
+
+    broken.register_peer(tx2.clone()).await;
+
+    let handle1 = tokio::spawn(broken.run());
+    let handle2 = tokio::spawn(observer.run());
+
+    tx1.send(AgentMessage::Task(
+        "sum".to_string(),
+        "What is 2 + 2? Reply with just the number.".to_string(),
+        None,
+    ))
+    .await?;
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    tx1.send(AgentMessage::Shutdown).await?;
+    tx2.send(AgentMessage::Shutdown).await?;
+    handle1.await?;
+    handle2.await?;
+
+    Ok(())
+}
+
+/// An example of three agents sharing a rate limiter that allows one provider call every 5 seconds.
+/// All three are given a task at once, but the logs show each one waiting about 5 seconds longer than the last.
+async fn rate_limit_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    let rate_limiter = RateLimiter::new(1, Duration::from_secs(5));
+
+    let mut senders = Vec::new();
+    let mut handles = Vec::new();
+
+    for name in ["Tom", "Richard", "Harry"] {
+        let (tx, rx) = mpsc::channel(100);
+        let agent = AutonomousAgent::new(
+            name.to_string(),
+            api_key.clone(),
+            rx,
+            Duration::from_secs(60),
+        )
+        .with_rate_limiter(rate_limiter.clone());
+
+        senders.push(tx);
+        handles.push(tokio::spawn(agent.run()));
+    }
+
+    for tx in &senders {
+        tx.send(AgentMessage::Task(
+            "sum".to_string(),
+            "What is 2 + 2? Reply with just the number.".to_string(),
+            None,
+        ))
+        .await?;
+    }
+
+    tokio::time::sleep(Duration::from_secs(20)).await;
+
+    for tx in &senders {
+        tx.send(AgentMessage::Shutdown).await?;
+    }
+    for handle in handles {
+        handle.await?;
+    }
+
+    Ok(())
+}
+
+/// An example of trace context following a task across agents.
+/// When Tom broadcasts his result, Richard handles it in a child of Tom's `handle_task` span,
+/// so the logs show Richard's `handle_result` span nested inside Tom's (and a tracing backend
+/// would show both as part of one trace).
+async fn trace_propagation_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (tx1, rx1) = mpsc::channel(100);
+    let (tx2, rx2) = mpsc::channel(100);
+    let tom = AutonomousAgent::new(
+        "Tom".to_string(),
+        api_key.clone(),
+        rx1,
+        Duration::from_secs(60),
+    );
+    let richard =
+        AutonomousAgent::new("Richard".to_string(), api_key, rx2, Duration::from_secs(60));
+
+    tom.register_peer(tx2.clone()).await;
+
+    let handle1 = tokio::spawn(tom.run());
+    let handle2 = tokio::spawn(richard.run());
+
+    tx1.send(AgentMessage::Task(
+        "sum".to_string(),
+        "What is 2 + 2? Reply with just the number.".to_string(),
+        None,
+    ))
+    .await?;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    tx1.send(AgentMessage::Shutdown).await?;
+    tx2.send(AgentMessage::Shutdown).await?;
     handle1.await?;
     handle2.await?;
-    handle3.await?;
+
+    Ok(())
+}
+
+/// An example of an agent that only works on one task at a time.
+/// The second task arrives while the first is still running, so it's rejected and whoever sent
+/// it is told he was busy, rather than the work piling up.
+async fn backpressure_example() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+    let (tx1, rx1) = mpsc::channel(100);
+    let tom = AutonomousAgent::new("Tom".to_string(), api_key, rx1, Duration::from_secs(60))
+        .with_max_in_flight(1);
+
+    let handle1 = tokio::spawn(tom.run());
+
+    // Tom replies to this channel if he has to turn a task down
+    let (reply_tx, mut reply_rx) = mpsc::channel(10);
+
+    tx1.send(AgentMessage::Task(
+        "essay".to_string(),
+        "Write a short essay on the history of distributed systems".to_string(),
+        Some(reply_tx.clone()),
+    ))
+    .await?;
+    tx1.send(AgentMessage::Task(
+        "sum".to_string(),
+        "What is 2 + 2? Reply with just the number.".to_string(),
+        Some(reply_tx),
+    ))
+    .await?;
+
+    if let Ok(Some(AgentMessage::Busy(from_id, task_id))) =
+        tokio::time::timeout(Duration::from_secs(20), reply_rx.recv()).await
+    {
+        println!("{from_id} was busy and rejected task {task_id}");
+    }
+
+    tokio::time::sleep(Duration::from_secs(20)).await;
+    tx1.send(AgentMessage::Shutdown).await?;
+    println!("{}", handle1.await?);
 
     Ok(())
 }
@@ -1,19 +1,16 @@
+use common::telemetry::{self, LogFormat};
 use observability::process_query;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info,rig=trace".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let _telemetry = telemetry::init_with_default_filter(LogFormat::Pretty, "info,rig=trace")?;
 
-    let response = process_query("Hello world!").await.unwrap();
-
-    println!("Response: {response}");
+    // To see an errored span, try running this with an invalid API key:
+    // `OPENAI_API_KEY=invalid cargo run --bin basic`
+    match process_query("Hello world!").await {
+        Ok(response) => println!("Response: {response}"),
+        Err(e) => eprintln!("Error: {e}"),
+    }
 
     Ok(())
 }
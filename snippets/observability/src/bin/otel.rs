@@ -1,46 +1,49 @@
+use common::telemetry::{self, LogFormat, OtlpConfig};
 use observability::process_query;
-use opentelemetry::trace::TracerProvider;
+use opentelemetry::global;
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
-use tracing::Level;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use opentelemetry_sdk::{Resource, metrics::SdkMeterProvider};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let exporter = opentelemetry_otlp::SpanExporter::builder()
+    let otlp_config = OtlpConfig::from_env();
+    match &otlp_config.endpoint {
+        Some(endpoint) => println!("Exporting to {endpoint} ({:?})", otlp_config.protocol),
+        None => println!(
+            "Exporting to the default endpoint ({:?})",
+            otlp_config.protocol
+        ),
+    }
+
+    // Spans are exported via the shared OpenTelemetry trace pipeline, which uses the same config
+    // (and samples spans using `OTEL_SAMPLE_RATIO`). It also prettifies the logs/spans that get
+    // outputted to `stdout`.
+    let telemetry = telemetry::init(LogFormat::Otel)?;
+
+    // Metrics are exported via a separate pipeline that periodically pushes to the OTel collector.
+    // This must be set globally before `process_query` is first called.
+    let mut metric_exporter = opentelemetry_otlp::MetricExporter::builder()
         .with_http()
-        .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
-        .build()?;
-    // Create a new OpenTelemetry trace pipeline that prints to stdout
-    let provider = SdkTracerProvider::builder()
-        .with_batch_exporter(exporter)
+        .with_protocol(otlp_config.protocol);
+    if let Some(endpoint) = otlp_config.signal_endpoint("metrics") {
+        metric_exporter = metric_exporter.with_endpoint(endpoint);
+    }
+    let metric_exporter = metric_exporter.build()?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metric_exporter)
         .with_resource(Resource::builder().with_service_name("rig-service").build())
         .build();
-    let tracer = provider.tracer("example");
+    global::set_meter_provider(meter_provider.clone());
 
-    // Create a tracing layer with the configured tracer
-    let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
-    let filter_layer = tracing_subscriber::filter::EnvFilter::builder()
-        .with_default_directive(Level::INFO.into())
-        .from_env_lossy();
+    // Each call increments the request counter and records its latency
+    for prompt in ["Hello world!", "What is Rig?"] {
+        let response = process_query(prompt).await.unwrap();
+        println!("Response: {response}");
+    }
 
-    // add a `fmt` layer that prettifies the logs/spans that get outputted to `stdout`
-    let fmt_layer = tracing_subscriber::fmt::layer().pretty();
-
-    // Create a multi-layer subscriber that filters for given traces,
-    // prettifies the logs/spans and then sends them to the OTel collector.
-    tracing_subscriber::registry()
-        .with(filter_layer)
-        .with(fmt_layer)
-        .with(otel_layer)
-        .init();
-
-    let response = process_query("Hello world!").await.unwrap();
-
-    println!("Response: {response}");
-
-    // Shutdown tracer provider on exit
-    let _ = provider.shutdown();
+    // Shutdown tracer and meter providers on exit (this also flushes any remaining spans and metrics)
+    drop(telemetry);
+    let _ = meter_provider.shutdown();
 
     Ok(())
 }
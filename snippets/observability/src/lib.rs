@@ -1,24 +1,108 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use common::models::openai::CHAT_MODEL;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram},
+};
 use rig::{
     client::{CompletionClient, ProviderClient},
     completion::Prompt,
     providers::openai,
 };
-use tracing::{info, instrument};
+use tracing::{Span, error, info, instrument};
+
+/// The maximum number of characters of a prompt or response to include in logs.
+/// Anything longer is truncated to avoid logging huge payloads.
+pub const MAX_LOGGED_CHARS: usize = 200;
+
+/// Metric instruments for `process_query`.
+/// If no global meter provider has been set (like in the `basic` binary), these are no-ops.
+struct QueryMetrics {
+    requests: Counter<u64>,
+    latency: Histogram<f64>,
+}
+
+/// Lazily creates the metric instruments from the global meter provider.
+/// This means the meter provider should be set before `process_query` is first called.
+fn metrics() -> &'static QueryMetrics {
+    static METRICS: OnceLock<QueryMetrics> = OnceLock::new();
 
-#[instrument(name = "process_user_query")]
+    METRICS.get_or_init(|| {
+        let meter = global::meter("rig-service");
+
+        QueryMetrics {
+            requests: meter
+                .u64_counter("process_query.requests")
+                .with_description("Number of queries processed")
+                .build(),
+            latency: meter
+                .f64_histogram("process_query.duration")
+                .with_description("How long each query took to process")
+                .with_unit("ms")
+                .build(),
+        }
+    })
+}
+
+#[instrument(
+    name = "process_user_query",
+    skip(user_input),
+    fields(
+        prompt = %truncate(user_input, MAX_LOGGED_CHARS),
+        duration_ms = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+        otel.status_message = tracing::field::Empty,
+    )
+)]
 pub async fn process_query(user_input: &str) -> Result<String, Box<dyn std::error::Error>> {
     info!("Processing user query");
 
     let openai_client = openai::Client::from_env();
 
     let agent = openai_client
-        .agent("gpt-5.2")
+        .agent(CHAT_MODEL)
         .preamble("You are a helpful assistant.")
         .build();
 
     // This completion call will emit spans automatically
-    let response = agent.prompt(user_input).await?;
+    let start = Instant::now();
+    let result = agent.prompt(user_input).await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    Span::current().record("duration_ms", duration_ms);
+    info!(duration_ms, "Completion finished");
+
+    let metrics = metrics();
+    metrics.requests.add(1, &[]);
+    metrics.latency.record(duration_ms as f64, &[]);
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            // Mark the span as errored using the tracing-opentelemetry special fields,
+            // so the failure shows up in whatever backend the spans are exported to
+            let span = Span::current();
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", e.to_string());
+            error!(error = %e, "Query failed");
 
-    info!("Query processed successfully");
+            return Err(e.into());
+        }
+    };
+
+    info!(
+        response = %truncate(&response, MAX_LOGGED_CHARS),
+        "Query processed successfully"
+    );
     Ok(response)
 }
+
+/// Truncates text to at most `max_chars` characters, adding an ellipsis if anything was cut off.
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}...", &text[..idx]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,54 @@
+//! A thin abstraction over where embedded documents are stored and searched, so the pipeline
+//! doesn't depend on a particular vector store. Only the in-memory store is implemented here,
+//! but a persistent store (like Qdrant or LanceDB) could be dropped in by implementing `DocStore`.
+use std::pin::Pin;
+
+use rig::{
+    embeddings::EmbeddingModel,
+    vector_store::in_memory_store::{InMemoryVectorIndex, InMemoryVectorStore},
+};
+
+use crate::store::EmbeddedDoc;
+use crate::{Doc, RetrievedDoc, retrieve_above_threshold};
+
+/// The future returned by `DocStore::search`.
+/// It's boxed so that `DocStore` can be used as a trait object.
+pub type SearchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>>> + 'a>>;
+
+/// Somewhere embedded documents can be searched.
+/// Stores are given their documents when they're created.
+pub trait DocStore {
+    /// Finds (up to) the `k` documents most similar to the query, best match first,
+    /// dropping any that score below `threshold`.
+    fn search<'a>(&'a self, query: &'a str, k: u64, threshold: f64) -> SearchFuture<'a>;
+}
+
+/// A `DocStore` backed by Rig's `InMemoryVectorStore`.
+/// Queries are embedded with the given model, which should be the one the documents were embedded with.
+pub struct InMemoryDocStore<M: EmbeddingModel> {
+    index: InMemoryVectorIndex<M, Doc>,
+}
+
+impl<M: EmbeddingModel> InMemoryDocStore<M> {
+    /// Indexes the documents up front, so that each search only has to embed the query.
+    pub fn new(model: M, documents: Vec<EmbeddedDoc>) -> Self {
+        let mut store = InMemoryVectorStore::default();
+        store.add_documents(documents);
+
+        Self {
+            index: store.index(model),
+        }
+    }
+
+    /// The underlying vector index, for techniques that need more than `DocStore::search`.
+    pub fn index(&self) -> &InMemoryVectorIndex<M, Doc> {
+        &self.index
+    }
+}
+
+impl<M: EmbeddingModel + Sync> DocStore for InMemoryDocStore<M> {
+    fn search<'a>(&'a self, query: &'a str, k: u64, threshold: f64) -> SearchFuture<'a> {
+        Box::pin(retrieve_above_threshold(query, &self.index, k, threshold))
+    }
+}
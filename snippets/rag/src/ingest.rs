@@ -0,0 +1,47 @@
+//! Helpers for ingesting documents into a vector store.
+use common::embeddings::DimensionValidator;
+use common::retry::retry_embed;
+use rig::embeddings::{EmbeddingError, EmbeddingModel, EmbeddingsBuilder};
+use tracing::info;
+
+use crate::Doc;
+use crate::store::EmbeddedDoc;
+
+/// Embeds documents in batches of `batch_size`, then merges the results.
+/// Providers limit how many inputs can be embedded in a single request, so large document sets
+/// need to be split up. The final batch may be smaller than `batch_size`.
+/// Returns an error if any embedding's dimension differs from the first one's.
+/// Each batch is retried (with backoff) if it fails for a transient reason, like a rate limit.
+pub async fn embed_in_batches<M: EmbeddingModel + Clone>(
+    documents: Vec<Doc>,
+    model: &M,
+    batch_size: usize,
+) -> Result<Vec<EmbeddedDoc>, Box<dyn std::error::Error>> {
+    let batch_size = batch_size.max(1);
+    let total_batches = documents.len().div_ceil(batch_size);
+    let mut embeddings = Vec::with_capacity(documents.len());
+    let mut validator = DimensionValidator::new();
+
+    for (i, batch) in documents.chunks(batch_size).enumerate() {
+        info!(
+            batch = i + 1,
+            total_batches,
+            documents = batch.len(),
+            "Embedding batch"
+        );
+
+        let batch_embeddings = retry_embed(3, || async {
+            EmbeddingsBuilder::new(model.clone())
+                .documents(batch.to_vec())
+                .map_err(|e| EmbeddingError::DocumentError(e.into()))?
+                .build()
+                .await
+        })
+        .await?;
+
+        validator.check_all(batch_embeddings.iter().flat_map(|(_, e)| e.iter()))?;
+        embeddings.extend(batch_embeddings);
+    }
+
+    Ok(embeddings)
+}
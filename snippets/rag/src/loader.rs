@@ -0,0 +1,88 @@
+//! Loads documents from disk, so that RAG can be pointed at a real corpus rather than a few
+//! hardcoded strings.
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+/// File extensions that are loaded when reading a directory.
+const SUPPORTED_EXTENSIONS: [&str; 2] = ["txt", "md"];
+
+/// The contents of a file, along with the path it was read from.
+pub struct LoadedDocument {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Reads the contents of a text file, or of every `.txt` and `.md` file in a directory
+/// (including its subdirectories).
+/// Binary files and empty files are skipped, as are symlinked directories (which could otherwise
+/// loop forever). Files are read in path order, so the same directory always produces the same
+/// documents (and so can reuse cached embeddings).
+pub fn load_documents(path: &Path) -> Result<Vec<LoadedDocument>, Box<dyn std::error::Error>> {
+    let mut documents = Vec::new();
+
+    if path.is_dir() {
+        load_dir(path, &mut documents)?;
+    } else {
+        documents.extend(load_file(path)?);
+    }
+
+    Ok(documents)
+}
+
+fn load_dir(
+    dir: &Path,
+    documents: &mut Vec<LoadedDocument>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_symlink() && path.is_dir() {
+            warn!(path = %path.display(), "Skipping symlinked directory");
+        } else if path.is_dir() {
+            load_dir(&path, documents)?;
+        } else if has_supported_extension(&path) {
+            documents.extend(load_file(&path)?);
+        } else {
+            debug!(path = %path.display(), "Skipping unsupported file");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a single file, returning `None` if it's empty or doesn't look like text.
+fn load_file(path: &Path) -> Result<Option<LoadedDocument>, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+
+    // Text files don't contain null bytes, so one is a good sign that a file is binary
+    // even if it has a `.txt` extension
+    if bytes.contains(&0) {
+        warn!(path = %path.display(), "Skipping binary file");
+        return Ok(None);
+    }
+
+    let Ok(text) = String::from_utf8(bytes) else {
+        warn!(path = %path.display(), "Skipping file that isn't valid UTF-8");
+        return Ok(None);
+    };
+
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(LoadedDocument {
+        path: path.to_path_buf(),
+        text: text.to_string(),
+    }))
+}
+
+fn has_supported_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
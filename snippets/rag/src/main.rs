@@ -1,85 +1,981 @@
 //! This example showcases RAG usage in Rig.
 //! Documents are created, embedded using OpenAI's embedding endpoint and inserted into the in-memory
 //! vector store implementation.
+//!
+//! Each document carries some metadata (its source and category), which is used to scope retrieval
+//! to a given category after the vector search has taken place.
+//!
+//! The query, number of samples and similarity threshold can be changed from the command line.
+//! By default, up to 2 documents scoring at least 0.8 are given to the model (at least 5 candidates
+//! are retrieved, so that there are still enough left after filtering and re-ranking).
+//! Lowering the threshold and raising the number of samples uses more documents for the same query,
+//! for example:
+//! `cargo run -p rag -- --query "What is a vector store?" --samples 2 --threshold 0.8`
+//! `cargo run -p rag -- --query "What is a vector store?" --samples 5 --threshold 0.3`
+//!
+//! Rather than using the built-in example documents, `--docs` loads every `.txt` and `.md` file
+//! from a file or directory. Try it with the small corpus in `rag/docs`:
+//! `cargo run -p rag -- --docs rag/docs --query "What is an embedding?"`
+//!
+//! To see exactly what would be sent to the model (including the retrieved documents), use dry-run mode.
+//! Documents are still retrieved (so an API key is needed, unless the embeddings are cached), but
+//! the provider check and re-ranking are skipped and the final request is printed rather than sent:
+//! `RIG_DRY_RUN=1 cargo run -p rag -- --query "What is Rig?"`
+use common::completion::first_text;
+use common::dry_run;
+use common::embedding_cache::CachedEmbeddingModel;
+use common::health::verify_client;
+use common::limits::max_output_tokens;
+use common::mock::{self, MockEmbeddingModel};
+use common::models::openai::{CHAT_MODEL, EMBED_MODEL, SMALL_CHAT_MODEL};
+use common::telemetry::{self, LogFormat};
+use doc_store::{DocStore, InMemoryDocStore};
+use futures::StreamExt;
+use metric::{Metric, rerank_by_metric};
 use rig::{
+    Embed,
     agent::Text,
     client::{CompletionClient, EmbeddingsClient, ProviderClient},
-    completion::{CompletionModel, Document},
-    embeddings::EmbeddingsBuilder,
-    providers::openai::{Client, TEXT_EMBEDDING_ADA_002},
-    vector_store::{VectorSearchRequest, VectorStoreIndex, in_memory_store::InMemoryVectorStore},
+    completion::{AssistantContent, CompletionModel, CompletionRequest, Document, Message, Prompt},
+    embeddings::{EmbedError, Embedding, EmbeddingModel, TextEmbedder, distance::VectorDistance},
+    providers::openai::{
+        Client,
+        responses_api::{self, ResponseStatus, ResponsesCompletionModel},
+    },
+    streaming::StreamedAssistantContent,
+    vector_store::{VectorSearchRequest, VectorStoreIndex, in_memory_store::InMemoryVectorIndex},
 };
+use search_tool::SearchTool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+mod doc_store;
+mod ingest;
+mod loader;
+mod metric;
+mod search_tool;
+mod store;
+
+/// Where embedded documents are persisted between runs.
+const EMBEDDINGS_CACHE_PATH: &str = "rag_embeddings.json";
+
+/// Where the embeddings of individual texts are cached between runs.
+const EMBEDDING_TEXT_CACHE_PATH: &str = "rag_embedding_cache.json";
+
+/// How many documents to embed per request.
+/// OpenAI accepts far more than this (we'd typically use 96), but a small batch size
+/// means our handful of example documents get split across multiple batches.
+const EMBEDDING_BATCH_SIZE: usize = 4;
+
+/// A document with some metadata attached.
+/// Only the text is embedded - the metadata is stored alongside it so results can be filtered.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+struct Doc {
+    text: String,
+    source: String,
+    category: String,
+}
+
+impl Doc {
+    fn new(text: &str, source: &str, category: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            source: source.to_string(),
+            category: category.to_string(),
+        }
+    }
+}
+
+impl Embed for Doc {
+    fn embed(&self, embedder: &mut TextEmbedder) -> Result<(), EmbedError> {
+        embedder.embed(self.text.clone());
+        Ok(())
+    }
+}
+
+/// How many candidates are retrieved (at least) before filtering and re-ranking.
+/// Only the best `--samples` of them are given to the model.
+const RERANK_CANDIDATES: u64 = 5;
+
+/// Options that can be set from the command line.
+struct CliArgs {
+    query: String,
+    samples: u64,
+    threshold: f64,
+    docs: Option<PathBuf>,
+}
+
+impl CliArgs {
+    /// Parses `--query`, `--samples`, `--threshold` and `--docs` from the command line.
+    /// Any option that isn't given uses its default.
+    fn parse() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut cli_args = Self {
+            query: "What is Rig?".to_string(),
+            samples: 2,
+            threshold: 0.8,
+            docs: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {arg}"))?;
+
+            match arg.as_str() {
+                "--query" => cli_args.query = value,
+                "--samples" => cli_args.samples = value.parse()?,
+                "--threshold" => cli_args.threshold = value.parse()?,
+                "--docs" => cli_args.docs = Some(PathBuf::from(value)),
+                _ => return Err(format!("Unknown argument: {arg}").into()),
+            }
+        }
+
+        Ok(cli_args)
+    }
+}
+
+/// A document returned from a search, along with how well it matched the query.
+#[derive(Clone, Debug)]
+struct RetrievedDoc {
+    score: f64,
+    id: String,
+    text: String,
+    source: String,
+    category: String,
+}
+
+impl RetrievedDoc {
+    /// Creates a `RetrievedDoc` from a single `top_n` result.
+    fn from_result((score, id, doc): (f64, String, Doc)) -> Self {
+        Self {
+            score,
+            id,
+            text: doc.text,
+            source: doc.source,
+            category: doc.category,
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let openai_client = Client::from_env();
-    let mut vector_store = InMemoryVectorStore::default();
+    // Pipeline progress is logged as structured JSON, while the results themselves are printed as-is
+    let _telemetry = telemetry::init(LogFormat::Json)?;
+    let args = CliArgs::parse()?;
 
     // Define documents to index
     let documents = vec![
-        "Rig is a Rust library for building LLM-powered applications.",
-        "RAG combines retrieval and generation for better accuracy.",
-        "Vector stores enable semantic search over documents.",
+        Doc::new(
+            "Rig is a Rust library for building LLM-powered applications.",
+            "docs.rig.rs",
+            "docs",
+        ),
+        // An overlapping chunk that says almost exactly the same thing as the one above
+        Doc::new(
+            "Rig is a Rust library for building applications powered by LLMs.",
+            "docs.rig.rs",
+            "docs",
+        ),
+        Doc::new(
+            "Rig is the fastest way to ship LLM apps in Rust - try it today!",
+            "twitter.com",
+            "marketing",
+        ),
+        Doc::new(
+            "RAG combines retrieval and generation for better accuracy.",
+            "book.rig.rs",
+            "docs",
+        ),
+        Doc::new(
+            "Vector stores enable semantic search over documents.",
+            "book.rig.rs",
+            "docs",
+        ),
+        Doc::new(
+            "A rig is the equipment used to drill oil and gas wells.",
+            "wikipedia.org",
+            "docs",
+        ),
+        Doc::new(
+            "Rig provides a unified interface over LLM providers like OpenAI and Anthropic.",
+            "docs.rig.rs",
+            "docs",
+        ),
+        Doc::new(
+            "Compiler error E0499 means a value was mutably borrowed more than once at a time.",
+            "doc.rust-lang.org",
+            "docs",
+        ),
     ];
 
-    let embed_model = openai_client.embedding_model(TEXT_EMBEDDING_ADA_002);
+    // Use documents loaded from disk instead, if we've been given any
+    let documents = match &args.docs {
+        Some(path) => {
+            // Each document's source is the file it came from, so that answers can be traced back to it
+            let documents: Vec<Doc> = loader::load_documents(path)?
+                .iter()
+                .map(|doc| Doc::new(&doc.text, &doc.path.display().to_string(), "docs"))
+                .collect();
+            info!(path = %path.display(), documents = documents.len(), "Loaded documents");
+            documents
+        }
+        None => documents,
+    };
+
+    // With `RIG_MOCK=1`, only the vector search runs, using a mock embedding model
+    // so that no API key is needed
+    if mock::is_enabled() {
+        return mock_search(documents, &args).await;
+    }
+
+    let openai_client = Client::from_env();
+    // If the provider can't be reached (for example, during an outage or with an invalid
+    // API key), we can still answer with a plain keyword search.
+    // Try running this with `OPENAI_API_KEY=invalid` to see the fallback.
+    // The check is itself a completion request, so it's skipped in dry-run mode.
+    if !dry_run::is_enabled()
+        && let Err(e) = verify_client(&openai_client, SMALL_CHAT_MODEL).await
+    {
+        warn!(error = %e, "Provider unavailable, falling back to keyword search");
+        print_keyword_results(&documents, &args.query);
+        return Ok(());
+    }
+
+    // Individual texts are cached too, so that when the document set changes, only the documents
+    // that are new (or have been edited) need to be embedded
+    let embed_model = CachedEmbeddingModel::load(
+        openai_client.embedding_model(EMBED_MODEL),
+        EMBED_MODEL,
+        EMBEDDING_TEXT_CACHE_PATH,
+    )?;
+
+    // Load previously created embeddings if the documents haven't changed since the last run.
+    // Otherwise, create embeddings and save them for next time.
+    let cache_path = Path::new(EMBEDDINGS_CACHE_PATH);
+    let hash = store::hash_documents(EMBED_MODEL, embed_model.ndims(), &documents);
+
+    let query_text = args.query.as_str();
+    let category = "docs";
+
+    let embeddings = match store::load(cache_path, hash)? {
+        Some(embeddings) => {
+            info!(
+                path = EMBEDDINGS_CACHE_PATH,
+                documents = embeddings.len(),
+                "Loaded cached embeddings"
+            );
+            embeddings
+        }
+        None => {
+            match ingest::embed_in_batches(documents.clone(), &embed_model, EMBEDDING_BATCH_SIZE)
+                .await
+            {
+                Ok(embeddings) => {
+                    store::save(cache_path, hash, &embeddings)?;
+                    embed_model.save()?;
+                    let (hits, misses) = embed_model.stats();
+                    info!(hits, misses, "Embedded documents using the text cache");
+                    info!(
+                        path = EMBEDDINGS_CACHE_PATH,
+                        documents = embeddings.len(),
+                        "Saved embeddings"
+                    );
+                    embeddings
+                }
+                // If the embedding API is unavailable (for example, during a provider outage or
+                // with an invalid API key), we can still answer with a plain keyword search
+                Err(e) => {
+                    warn!(error = %e, "Embedding failed, falling back to keyword search");
+                    print_keyword_results(&documents, query_text);
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    // The initial search only needs a `DocStore`, so any store could be used here.
+    // The more advanced techniques below need direct access to the in-memory vector index.
+    let doc_store = InMemoryDocStore::new(embed_model.clone(), embeddings);
+    let vector_idx = doc_store.index();
+
+    // The search tool needs an index of its own, so it's given a copy of the store
+    let search_idx = vector_idx.store.clone().index(embed_model);
+    println!("Prompt: {query_text} (category: {category})");
+
+    // Over-fetch here so that we still have enough results left after filtering by category
+    // and re-ranking.
+    let candidates = args.samples.max(RERANK_CANDIDATES);
+    let results = doc_store
+        .search(query_text, candidates, args.threshold)
+        .await?;
+
+    info!(results = results.len(), "Vector search complete");
+
+    results.iter().for_each(|doc| {
+        println!(
+            "Score: {}, ID: {}, Category: {}, Content: {}",
+            doc.score, doc.id, doc.category, doc.text
+        );
+    });
 
-    // Create embeddings and add to vector store
-    let embeddings = EmbeddingsBuilder::new(embed_model.clone())
-        .documents(documents)?
+    // Only keep results from the requested category.
+    // The marketing copy about Rig is semantically very close to the query, but gets excluded here.
+    let results = filter_by_category(results, category, candidates as usize);
+    info!(
+        results = results.len(),
+        category, "Filtered results by category"
+    );
+
+    // Drop chunks that are near-identical to a better-scoring chunk, so they don't waste context
+    let results = dedup_near_duplicates(results, vector_idx, 0.95);
+    info!(results = results.len(), "Removed near-duplicates");
+
+    // Re-rank the candidates with a cheaper model, then keep the best few.
+    // Vector similarity can't tell an oil rig from the Rig library, but an LLM can.
+    // Re-ranking sends a completion request per candidate, so in dry-run mode the vector order is kept.
+    let rerank_model = openai_client.completion_model(SMALL_CHAT_MODEL);
+    let mut results = if dry_run::is_enabled() {
+        results
+    } else {
+        rerank(query_text, results, &rerank_model).await?
+    };
+    results.truncate(args.samples as usize);
+
+    println!("Results after re-ranking:");
+    results.iter().for_each(|doc| {
+        println!(
+            "Score: {}, ID: {}, Content: {}",
+            doc.score, doc.id, doc.text
+        );
+    });
+
+    // The results are kept around (rather than moved into the documents) so we can show them as sources later
+    let documents = to_documents(&results);
+
+    if results.is_empty() {
+        println!(
+            "No relevant documents found (threshold: {}). Answering without retrieved documents.",
+            args.threshold
+        );
+    }
+
+    let completion_model = openai_client.completion_model(CHAT_MODEL);
+
+    let max_tokens = max_output_tokens();
+    let request = rag_request(&completion_model, query_text, documents.clone(), max_tokens);
+
+    // With `RIG_DRY_RUN=1`, the request (including the retrieved documents) is printed rather than sent.
+    // Retrieval still runs as normal, so the documents are exactly the ones that would be sent.
+    if let Some(json) = dry_run::maybe_dry_run(CHAT_MODEL, &request) {
+        println!("Dry run request:\n{json}");
+        return Ok(());
+    }
+
+    let mut stream = completion_model.stream(request).await?;
+
+    let mut response_text = String::new();
+
+    // Print each text delta as soon as it arrives
+    print!("Response: ");
+    while let Some(chunk) = stream.next().await {
+        match chunk? {
+            StreamedAssistantContent::Text(Text { text }) => {
+                print!("{text}");
+                std::io::stdout().flush()?;
+                response_text.push_str(&text);
+            }
+            // A streamed response doesn't say why it finished, but if it used every token it was
+            // allowed then it was almost certainly cut off
+            StreamedAssistantContent::Final(res) => {
+                if max_tokens.is_some_and(|max_tokens| res.usage.output_tokens >= max_tokens) {
+                    println!();
+                    println!("Response truncated (length limit)");
+                }
+            }
+            // Reasoning (and anything else that isn't part of the answer) is only logged,
+            // so it doesn't end up in the middle of the streamed answer
+            other => debug!(item = ?other, "Received non-text stream item"),
+        }
+    }
+    println!();
+
+    let cited = extract_citations(&response_text, &results);
+
+    println!("Sources:");
+    if results.is_empty() {
+        println!("- none");
+    }
+    for doc in &results {
+        let marker = if cited.contains(&doc.id.as_str()) {
+            " (cited)"
+        } else {
+            ""
+        };
+        println!("- {} (score: {:.3}){marker}", doc.id, doc.score);
+    }
+
+    truncation_example(&completion_model, query_text, documents).await?;
+
+    // Rather than retrieving documents ourselves, we can give the agent a search tool
+    // and let it decide when (and what) to search for
+    let rag_agent = openai_client
+        .agent(CHAT_MODEL)
+        .preamble(
+            "Answer the user's question. Use the search tool to find relevant documents first, \
+            and cite sources by id in square brackets, for example [doc0].",
+        )
+        .tool(SearchTool::new(search_idx, 3))
+        .build();
+
+    // Allow an extra turn so the agent can answer after receiving the search results
+    let response = rag_agent.prompt(query_text).multi_turn(2).await?;
+    println!("Agentic RAG response: {response}");
+
+    // Pure vector search can struggle with exact terms like error codes.
+    // Blending in a keyword score makes sure the document containing the exact code ranks first.
+    let keyword_query = "What does E0499 mean?";
+    println!("Hybrid search for: {keyword_query}");
+
+    for (alpha, label) in [(1.0, "Vector only"), (0.5, "Hybrid")] {
+        let results = hybrid_search(vector_idx, keyword_query, alpha, 3).await?;
+        println!("{label} (alpha = {alpha}):");
+        results.iter().for_each(|doc| {
+            println!(
+                "Score: {:.3}, ID: {}, Content: {}",
+                doc.score, doc.id, doc.text
+            );
+        });
+    }
+
+    // A single query can miss relevant documents that are phrased differently.
+    // Generating a few paraphrases and searching with each of them widens the net.
+    let vague_query = "Which AI companies can I use?";
+    println!("Multi-query search for: {vague_query}");
+
+    let single_results = retrieve(vague_query, vector_idx, 1).await?;
+    println!("Single query returned {} result(s):", single_results.len());
+    single_results.iter().for_each(|doc| {
+        println!(
+            "Score: {:.3}, ID: {}, Content: {}",
+            doc.score, doc.id, doc.text
+        );
+    });
+
+    let variations = generate_query_variations(vague_query, &rerank_model).await?;
+    let multi_results = multi_query_search(vector_idx, vague_query, &variations, 1).await?;
+    println!("Multi-query returned {} result(s):", multi_results.len());
+    multi_results.iter().for_each(|doc| {
+        println!(
+            "Score: {:.3}, ID: {}, Content: {}",
+            doc.score, doc.id, doc.text
+        );
+    });
+
+    similarity_metric_example();
+
+    no_results_example(vector_idx).await?;
+
+    Ok(())
+}
+
+/// Converts retrieved documents into documents for a completion request, keeping their metadata.
+fn to_documents(results: &[RetrievedDoc]) -> Vec<Document> {
+    results
+        .iter()
+        .map(|doc| Document {
+            id: doc.id.clone(),
+            text: doc.text.clone(),
+            additional_props: HashMap::from([
+                ("source".to_string(), doc.source.clone()),
+                ("category".to_string(), doc.category.clone()),
+            ]),
+        })
+        .collect()
+}
+
+/// Assembles the request that answers the query using the retrieved documents.
+fn rag_request<M: CompletionModel>(
+    model: &M,
+    query: &str,
+    documents: Vec<Document>,
+    max_tokens: Option<u64>,
+) -> CompletionRequest {
+    // With no documents to go on, the model would happily make up an answer (and citations).
+    // Instead, we have the model answer from its own knowledge, saying so.
+    let preamble = if documents.is_empty() {
+        "Answer the user's question from your own knowledge. \
+        No documents were found for this question, so start your answer by saying so."
+    } else {
+        "Answer the user's question using the provided documents. \
+        Cite sources by id in square brackets, for example [doc0]."
+    };
+
+    model
+        .completion_request(query)
+        .preamble(preamble.to_string())
+        .documents(documents)
+        .max_tokens_opt(max_tokens)
         .build()
+}
+
+/// Embeds the documents with a mock embedding model, then searches them.
+/// The results are the same on every run, and no requests are made to a provider.
+async fn mock_search(
+    documents: Vec<Doc>,
+    args: &CliArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let embed_model = MockEmbeddingModel::default();
+    let embeddings =
+        ingest::embed_in_batches(documents, &embed_model, EMBEDDING_BATCH_SIZE).await?;
+
+    let doc_store: Box<dyn DocStore> = Box::new(InMemoryDocStore::new(embed_model, embeddings));
+
+    println!("Prompt: {} (mock embeddings)", args.query);
+    let results = doc_store
+        .search(&args.query, args.samples, args.threshold)
         .await?;
 
-    vector_store.add_documents(embeddings);
+    results.iter().for_each(|doc| {
+        println!(
+            "Score: {:.3}, ID: {}, Content: {}",
+            doc.score, doc.id, doc.text
+        );
+    });
 
-    // Create a vector index from the in-memory vector store
-    let vector_idx = vector_store.index(embed_model);
-    let query_text = "What is Rig?";
-    println!("Prompt: {query_text}");
+    Ok(())
+}
+
+/// Why a response finished early, if it did.
+/// For example, a response that ran out of output tokens has the reason `max_output_tokens`.
+fn truncation_reason(response: &responses_api::CompletionResponse) -> Option<&str> {
+    if !matches!(response.status, ResponseStatus::Incomplete) {
+        return None;
+    }
+
+    Some(
+        response
+            .incomplete_details
+            .as_ref()
+            .map_or("unknown", |details| details.reason.as_str()),
+    )
+}
+
+/// Answers a query, and if the answer gets cut off, asks the model to carry on from where it stopped.
+/// Gives up (returning what it has so far) after `max_continuations` continuations.
+async fn complete_with_continuations(
+    model: &ResponsesCompletionModel,
+    query: &str,
+    documents: Vec<Document>,
+    max_tokens: u64,
+    max_continuations: usize,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut answer = String::new();
+    let mut history = Vec::new();
+    let mut prompt = query.to_string();
+
+    for continuation in 0..=max_continuations {
+        let response = model
+            .completion_request(prompt.as_str())
+            .preamble("Answer the user's question using the provided documents.".to_string())
+            .documents(documents.clone())
+            .messages(history.clone())
+            .max_tokens(max_tokens)
+            .send()
+            .await?;
+
+        let text = response
+            .choice
+            .iter()
+            .filter_map(|content| match content {
+                AssistantContent::Text(Text { text }) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<String>();
+        answer.push_str(&text);
+
+        let Some(reason) = truncation_reason(&response.raw_response) else {
+            return Ok(answer);
+        };
+
+        if reason == "max_output_tokens" {
+            println!("Response truncated (length limit)");
+        } else {
+            println!("Response truncated ({reason})");
+        }
+
+        if continuation == max_continuations {
+            break;
+        }
+
+        // Replay the conversation so far so the model can pick up where it left off
+        history.push(Message::user(prompt));
+        history.push(Message::assistant(text));
+        prompt = "Continue exactly where you left off, without repeating anything.".to_string();
+    }
+
+    warn!(
+        max_continuations,
+        "Response still truncated after continuing"
+    );
+    Ok(answer)
+}
+
+/// Shows how to detect a response that was cut off for running out of tokens.
+/// The token limit is set deliberately low, so the first response is truncated and has to be continued.
+async fn truncation_example(
+    model: &ResponsesCompletionModel,
+    query: &str,
+    documents: Vec<Document>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Answering with a 32 token limit: {query}");
+    let answer = complete_with_continuations(model, query, documents, 32, 3).await?;
+    println!("Response (after continuing): {answer}");
+
+    Ok(())
+}
+
+/// Shows what happens when nothing scores above the threshold.
+/// Rather than sending an empty document list to the model, the user is told that nothing was found.
+async fn no_results_example<I: VectorStoreIndex>(
+    index: &I,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let query = "How do I bake sourdough bread?";
+    let threshold = 0.99;
+    println!("Searching for: {query} (threshold: {threshold})");
+
+    let results = retrieve_above_threshold(query, index, 5, threshold).await?;
+
+    if results.is_empty() {
+        println!(
+            "No relevant documents found. Try lowering the threshold or rephrasing the query."
+        );
+    } else {
+        println!("Found {} result(s)", results.len());
+    }
 
-    let query = VectorSearchRequest::builder()
-        .query(query_text)
-        .samples(2)
-        .threshold(0.8)
+    Ok(())
+}
+
+/// Shows how the choice of similarity metric can change the order of results.
+/// The embeddings here are crafted by hand (rather than coming from a model) to make the difference obvious.
+fn similarity_metric_example() {
+    let embedding = |document: &str, vec: Vec<f64>| Embedding {
+        document: document.to_string(),
+        vec,
+    };
+    let doc = |id: &str, text: &str| RetrievedDoc {
+        score: 0.0,
+        id: id.to_string(),
+        text: text.to_string(),
+        source: "example".to_string(),
+        category: "docs".to_string(),
+    };
+
+    let query_embedding = embedding("query", vec![1.0, 0.0]);
+
+    // `long` points in a slightly different direction to the query but has a much larger magnitude,
+    // while `short` points in almost exactly the same direction as the query
+    let candidates = vec![
+        (
+            doc("long", "A long document"),
+            embedding("long", vec![10.0, 10.0]),
+        ),
+        (
+            doc("short", "A short document"),
+            embedding("short", vec![1.0, 0.1]),
+        ),
+    ];
+
+    // Cosine and euclidean rank `short` first, but dot product ranks `long` first
+    for metric in [Metric::Cosine, Metric::DotProduct, Metric::Euclidean] {
+        let results = rerank_by_metric(&query_embedding, candidates.clone(), metric);
+        println!("{metric:?}:");
+        results.iter().for_each(|doc| {
+            println!("Score: {:.3}, ID: {}", doc.score, doc.id);
+        });
+    }
+}
+
+/// Finds the `k` documents most similar to the query, best match first.
+async fn retrieve<I: VectorStoreIndex>(
+    query: &str,
+    index: &I,
+    k: u64,
+) -> Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>> {
+    let req = VectorSearchRequest::builder()
+        .query(query)
+        .samples(k)
         .build()?;
 
-    // Query the vector store
-    let results = vector_idx.top_n::<String>(query).await?;
+    let results = index.top_n::<Doc>(req).await?;
+
+    // The in-memory vector store doesn't return its results in any particular order
+    let mut results: Vec<RetrievedDoc> =
+        results.into_iter().map(RetrievedDoc::from_result).collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    Ok(results)
+}
 
-    if !results.is_empty() {
-        println!("Found {} results", results.len());
+/// Like `retrieve`, but drops any documents scoring below `threshold`.
+/// The in-memory vector store doesn't apply a threshold itself, so we do it here instead.
+async fn retrieve_above_threshold<I: VectorStoreIndex>(
+    query: &str,
+    index: &I,
+    k: u64,
+    threshold: f64,
+) -> Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>> {
+    let mut results = retrieve(query, index, k).await?;
+    results.retain(|doc| doc.score >= threshold);
+
+    if results.is_empty() {
+        warn!(query, threshold, "No documents found above threshold");
     }
 
-    results.iter().for_each(|(score, doc_id, doc)| {
-        println!("Score: {}, ID: {}, Content: {}", score, doc_id, doc);
-    });
+    Ok(results)
+}
+
+/// Asks the model to generate three paraphrases of the given query.
+async fn generate_query_variations<M: CompletionModel>(
+    query: &str,
+    model: &M,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let prompt = format!(
+        "Write 3 different rephrasings of the following search query. \
+        Respond with one rephrasing per line and nothing else.\n\nQuery: {query}"
+    );
+
+    let response = model.completion_request(&prompt).send().await?;
+
+    let text = first_text(&response.choice).ok_or("Model returned non-text response")?;
+
+    let variations: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(3)
+        .map(str::to_string)
+        .collect();
+
+    println!("Query variations: {variations:?}");
+
+    Ok(variations)
+}
+
+/// Runs `top_n` for the original query and each of its variations,
+/// then merges the results, removing duplicates by document ID (keeping the best score).
+async fn multi_query_search<I: VectorStoreIndex>(
+    index: &I,
+    query: &str,
+    variations: &[String],
+    samples: u64,
+) -> Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>> {
+    let mut merged: HashMap<String, RetrievedDoc> = HashMap::new();
 
-    let documents: Vec<Document> = results
+    for q in std::iter::once(query).chain(variations.iter().map(String::as_str)) {
+        for doc in retrieve(q, index, samples).await? {
+            let score = doc.score;
+            let entry = merged.entry(doc.id.clone()).or_insert(doc);
+            entry.score = entry.score.max(score);
+        }
+    }
+
+    let mut results: Vec<RetrievedDoc> = merged.into_values().collect();
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    Ok(results)
+}
+
+/// Combines vector similarity with a simple keyword-overlap score.
+/// `alpha` blends the two: `1.0` is pure vector search and `0.0` is pure keyword search.
+async fn hybrid_search<I: VectorStoreIndex>(
+    index: &I,
+    query: &str,
+    alpha: f64,
+    limit: usize,
+) -> Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>> {
+    // Score every document so that keyword matches ranked low by the vector search aren't missed.
+    // For large stores you'd fetch a larger candidate set instead.
+    let mut results: Vec<RetrievedDoc> = retrieve(query, index, 100)
+        .await?
         .into_iter()
-        .map(|(_, id, doc)| {
-            Document {
-                id,
-                text: doc,
-                additional_props: std::collections::HashMap::new(), // whatever extra properties you want here
-            }
+        .map(|mut doc| {
+            doc.score = alpha * doc.score + (1.0 - alpha) * keyword_score(query, &doc.text);
+            doc
         })
         .collect();
 
-    let completion_model = openai_client.completion_model("gpt-5.2");
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
 
-    let res = completion_model
-        .completion_request(query_text)
-        .documents(documents)
-        .send()
-        .await
-        .unwrap();
-
-    for content in res.choice {
-        match content {
-            rig::message::AssistantContent::Text(Text { text }) => println!("Response: {text}"),
-            other => println!("Received non-text response: {other:?}"),
+    Ok(results)
+}
+
+/// Prints the top keyword search results for a query.
+/// Used in place of the full example when embeddings can't be created.
+fn print_keyword_results(documents: &[Doc], query: &str) {
+    println!("Prompt: {query}");
+    for doc in keyword_search(documents, query, 2) {
+        println!(
+            "Score: {:.3}, ID: {}, Content: {}",
+            doc.score, doc.id, doc.text
+        );
+    }
+}
+
+/// Ranks documents by keyword score alone, without using embeddings.
+/// Documents are given the same IDs they would have in the vector store.
+fn keyword_search(documents: &[Doc], query: &str, limit: usize) -> Vec<RetrievedDoc> {
+    let mut results: Vec<RetrievedDoc> = documents
+        .iter()
+        .enumerate()
+        .map(|(i, doc)| {
+            let score = keyword_score(query, &doc.text);
+            RetrievedDoc::from_result((score, format!("doc{i}"), doc.clone()))
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+
+    results
+}
+
+/// The fraction of query terms that appear in the document (case-insensitive).
+fn keyword_score(query: &str, text: &str) -> f64 {
+    let tokenize = |s: &str| {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(str::to_lowercase)
+            .collect::<std::collections::HashSet<_>>()
+    };
+
+    let query_terms = tokenize(query);
+    if query_terms.is_empty() {
+        return 0.0;
+    }
+
+    let doc_terms = tokenize(text);
+    let matches = query_terms.intersection(&doc_terms).count();
+
+    matches as f64 / query_terms.len() as f64
+}
+
+/// Re-ranks retrieved documents by asking a (preferably cheap) model to score each document's
+/// relevance to the query. Returns the documents sorted by the model's score, best first.
+/// The original vector similarity score is kept in each result.
+/// If the model doesn't respond with a score, the document's vector score is used instead.
+async fn rerank<M: CompletionModel>(
+    query: &str,
+    docs: Vec<RetrievedDoc>,
+    model: &M,
+) -> Result<Vec<RetrievedDoc>, Box<dyn std::error::Error>> {
+    let mut scored = Vec::with_capacity(docs.len());
+
+    for doc in docs {
+        let prompt = format!(
+            "On a scale of 0 to 10, how relevant is the following document to the query? \
+            Respond with only the number.\n\nQuery: {query}\n\nDocument: {}",
+            doc.text
+        );
+
+        let response = model.completion_request(&prompt).send().await?;
+
+        let text = first_text(&response.choice).unwrap_or_default();
+
+        // Models don't always reply with just the number (eg, "8/10" or "Score: 8"),
+        // so the first number in the reply is used. If there isn't one, the document keeps
+        // its vector score (scaled to 0-10) rather than failing the whole search.
+        let relevance = first_number(text).unwrap_or_else(|| {
+            warn!(
+                id = doc.id,
+                reply = text,
+                "Reranking model returned no score"
+            );
+            doc.score * 10.0
+        });
+
+        scored.push((relevance, doc));
+    }
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    Ok(scored.into_iter().map(|(_, doc)| doc).collect())
+}
+
+/// The first number in some text, for example `8` in "Score: 8/10".
+fn first_number(text: &str) -> Option<f64> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let rest = &text[start..];
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+
+    rest[..end].trim_end_matches('.').parse().ok()
+}
+
+/// Removes results whose embedding has a cosine similarity above `max_similarity` with a result
+/// that has already been selected. Results are expected to be sorted best first, so the
+/// highest-scoring chunk out of a group of near-duplicates is the one that gets kept.
+fn dedup_near_duplicates<M: EmbeddingModel>(
+    results: Vec<RetrievedDoc>,
+    index: &InMemoryVectorIndex<M, Doc>,
+    max_similarity: f64,
+) -> Vec<RetrievedDoc> {
+    let embeddings: HashMap<&String, &Embedding> = index
+        .iter()
+        .map(|(id, (_, embeddings))| (id, embeddings.first_ref()))
+        .collect();
+
+    let mut selected: Vec<RetrievedDoc> = Vec::new();
+
+    for result in results {
+        let Some(embedding) = embeddings.get(&result.id) else {
+            selected.push(result);
+            continue;
+        };
+
+        let is_duplicate = selected.iter().any(|other| {
+            embeddings
+                .get(&other.id)
+                .is_some_and(|other| embedding.cosine_similarity(other, false) > max_similarity)
+        });
+
+        if is_duplicate {
+            info!(doc_id = %result.id, "Dropping near-duplicate chunk");
+        } else {
+            selected.push(result);
         }
     }
 
-    Ok(())
+    selected
+}
+
+/// Returns the IDs of any retrieved documents that the model cited in its response.
+/// Citations are expected in the form `[doc0]`, as requested in the preamble.
+fn extract_citations<'a>(response: &str, sources: &'a [RetrievedDoc]) -> Vec<&'a str> {
+    sources
+        .iter()
+        .map(|doc| doc.id.as_str())
+        .filter(|id| response.contains(&format!("[{id}]")))
+        .collect()
+}
+
+/// Keeps only the results matching the given category, up to `limit` results.
+/// Results from `top_n` are already sorted by score, so the best matches are kept.
+fn filter_by_category(
+    results: Vec<RetrievedDoc>,
+    category: &str,
+    limit: usize,
+) -> Vec<RetrievedDoc> {
+    results
+        .into_iter()
+        .filter(|doc| doc.category == category)
+        .take(limit)
+        .collect()
 }
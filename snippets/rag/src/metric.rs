@@ -0,0 +1,51 @@
+//! Re-ranking retrieved documents using a different similarity metric.
+//! `InMemoryVectorStore` always ranks by cosine similarity, so other metrics are applied after retrieval.
+use rig::embeddings::{Embedding, distance::VectorDistance};
+
+use crate::RetrievedDoc;
+
+/// How to measure the similarity between two embeddings.
+#[derive(Clone, Copy, Debug)]
+pub enum Metric {
+    /// Compares direction only, ignoring magnitude. The default for `InMemoryVectorStore`.
+    Cosine,
+    /// Compares direction and magnitude, so longer vectors score higher.
+    DotProduct,
+    /// The straight-line distance between two vectors. Closer vectors score higher.
+    Euclidean,
+}
+
+impl Metric {
+    /// Scores two embeddings, where a higher score always means more similar.
+    fn score(&self, a: &Embedding, b: &Embedding) -> f64 {
+        match self {
+            Metric::Cosine => a.cosine_similarity(b, false),
+            Metric::DotProduct => a.dot_product(b),
+            // Negate the distance, so that closer documents get a higher score
+            Metric::Euclidean => -a.euclidean_distance(b),
+        }
+    }
+}
+
+/// Re-scores each candidate against the query embedding using the given metric,
+/// returning the candidates sorted best first.
+///
+/// Note that OpenAI embeddings are normalized to a length of 1, in which case all three metrics
+/// agree on the ordering. The choice of metric matters for models that don't normalize their output.
+pub fn rerank_by_metric(
+    query_embedding: &Embedding,
+    candidates: Vec<(RetrievedDoc, Embedding)>,
+    metric: Metric,
+) -> Vec<RetrievedDoc> {
+    let mut results: Vec<RetrievedDoc> = candidates
+        .into_iter()
+        .map(|(mut doc, embedding)| {
+            doc.score = metric.score(query_embedding, &embedding);
+            doc
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    results
+}
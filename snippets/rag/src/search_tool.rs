@@ -0,0 +1,68 @@
+//! Exposes retrieval as a tool, so that an agent can decide for itself when (and what) to search,
+//! rather than us retrieving documents up front and injecting them into the prompt.
+use rig::{completion::ToolDefinition, tool::Tool, vector_store::VectorStoreIndex};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::retrieve;
+
+#[derive(Deserialize)]
+pub struct SearchArgs {
+    query: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Search failed: {0}")]
+pub struct SearchError(String);
+
+/// Searches a vector store index for documents relevant to a query.
+pub struct SearchTool<I> {
+    index: I,
+    /// How many documents to return per search.
+    limit: u64,
+}
+
+impl<I: VectorStoreIndex> SearchTool<I> {
+    pub fn new(index: I, limit: u64) -> Self {
+        Self { index, limit }
+    }
+}
+
+impl<I: VectorStoreIndex> Tool for SearchTool<I> {
+    const NAME: &'static str = "search_documents";
+    type Error = SearchError;
+    type Args = SearchArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Search the documentation for passages relevant to a query".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "What to search for"
+                    }
+                },
+                "required": ["query"],
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        println!("[tool-call] Searching for: {}", args.query);
+
+        let results = retrieve(&args.query, &self.index, self.limit)
+            .await
+            .map_err(|e| SearchError(e.to_string()))?;
+
+        // Each result is labelled with its ID and source, so the agent can cite it
+        Ok(results
+            .iter()
+            .map(|doc| format!("[{}] ({}) {}", doc.id, doc.source, doc.text))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
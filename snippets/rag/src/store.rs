@@ -0,0 +1,77 @@
+//! Helpers for persisting embedded documents to disk, so that they don't need to be re-embedded
+//! on every run.
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+};
+
+use rig::{OneOrMany, embeddings::Embedding};
+use serde::{Deserialize, Serialize};
+
+use crate::Doc;
+
+/// A document along with its embedding(s).
+pub type EmbeddedDoc = (Doc, OneOrMany<Embedding>);
+
+/// The embedded documents as stored on disk.
+/// The hash of the input documents is stored alongside the embeddings so we can tell if
+/// the document set (or embedding model) has changed since the embeddings were created.
+#[derive(Serialize, Deserialize)]
+struct StoredEmbeddings {
+    hash: u64,
+    documents: Vec<EmbeddedDoc>,
+}
+
+/// Hashes a set of documents, along with the model that embeds them.
+/// Embeddings from different models (or with a different number of dimensions) can't be compared,
+/// so changing the model changes the hash too.
+/// Note that `DefaultHasher` is only guaranteed to be stable within the same Rust version,
+/// so upgrading Rust may cause a one-off re-embed.
+pub fn hash_documents(model: &str, ndims: usize, documents: &[Doc]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    model.hash(&mut hasher);
+    ndims.hash(&mut hasher);
+
+    for doc in documents {
+        doc.text.hash(&mut hasher);
+        doc.source.hash(&mut hasher);
+        doc.category.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+/// Saves embedded documents (and the hash of the documents they were created from) to a JSON file.
+pub fn save(
+    path: &Path,
+    hash: u64,
+    documents: &[EmbeddedDoc],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stored = StoredEmbeddings {
+        hash,
+        documents: documents.to_vec(),
+    };
+
+    std::fs::write(path, serde_json::to_string(&stored)?)?;
+
+    Ok(())
+}
+
+/// Loads embedded documents from a JSON file.
+/// Returns `None` if the file doesn't exist or was created from a different set of documents.
+pub fn load(
+    path: &Path,
+    hash: u64,
+) -> Result<Option<Vec<EmbeddedDoc>>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let stored: StoredEmbeddings = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+    if stored.hash != hash {
+        return Ok(None);
+    }
+
+    Ok(Some(stored.documents))
+}
@@ -1,29 +1,514 @@
-//! This example showcases using a simple agent with a given name to send a request to an LLM.
+//! This example showcases using a simple agent with a given name to send a request to an LLM, first without
+//! reasoning and then with a high reasoning effort.
+//! The same prompt is then sent using the lower-level completion API, to show the metadata
+//! (finish reason, token usage and tool calls) that `prompt` hides.
+//! Next, a verbose prompt is sent with and without a cap on output tokens.
+//! The same request is then sent again, this time streaming the response as it is generated.
+//! An image is then sent alongside a text prompt, to a vision-capable model.
+//! Then, a typed value is extracted from a prompt using structured output.
+//! A circuit breaker is then shown failing fast after several (simulated) provider failures.
+//! Finally, an interactive multi-turn chat is started which keeps track of the conversation history.
+//!
+//! Passing `--json` instead sends a single request and prints the result as one line of JSON,
+//! so it can be piped into other tools, for example:
+//! `cargo run -p rig-api-call -- --json | jq -r .response`
+//!
+//! Setting `RIG_MOCK=1` also sends a single request, but to a mock model that returns a fixed
+//! response, so the example can be run without an API key:
+//! `RIG_MOCK=1 cargo run -p rig-api-call`
+//!
+//! Similarly, `RIG_DRY_RUN=1` prints the request that would be sent as JSON, without sending it:
+//! `RIG_DRY_RUN=1 cargo run -p rig-api-call`
+//!
+//! Setting `RIG_MAX_TOKENS` caps the output of every agent in this example, for example:
+//! `RIG_MAX_TOKENS=200 cargo run -p rig-api-call`
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+
+use common::circuit_breaker::{CircuitBreaker, CircuitBreakerError};
+use common::cost::estimate_cost;
+use common::dry_run::{self, DryRunCompletionModel};
+use common::health::verify_client_or_exit;
+use common::limits::{WithMaxTokens, max_output_tokens};
+use common::mock::{self, MockCompletionModel};
+use common::models::openai::{AGENT_MODEL, CHAT_MODEL, SMALL_CHAT_MODEL};
+use common::retry::{is_transient_message, retry};
+use futures::StreamExt;
+use rig::OneOrMany;
+use rig::agent::{Agent, AgentBuilder, MultiTurnStreamItem, PromptResponse, Text};
 use rig::client::{CompletionClient, ProviderClient};
-use rig::completion::Prompt;
+use rig::completion::{
+    AssistantContent, CompletionError, CompletionModel, Message, Prompt, PromptError,
+};
+use rig::message::{ImageDetail, ImageMediaType, UserContent};
 use rig::providers::openai::Client;
+use rig::providers::openai::responses_api::{AdditionalParameters, Reasoning, ReasoningEffort};
+use rig::streaming::{StreamedAssistantContent, StreamingPrompt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    call_agent().await?;
+    let output_mode = if std::env::args().any(|arg| arg == "--json") {
+        OutputMode::Json
+    } else {
+        OutputMode::Human
+    };
+
+    // Fail fast if the API key is invalid, rather than partway through the example.
+    // Try running this with `OPENAI_API_KEY=invalid` to see the error.
+    // In mock and dry-run modes, nothing is sent to the provider so there's no need to check.
+    if !mock::is_enabled() && !dry_run::is_enabled() {
+        verify_client_or_exit(&Client::from_env(), SMALL_CHAT_MODEL).await;
+    }
+
+    // In JSON mode, stdout is reserved for the JSON output so only one request is sent.
+    // In mock and dry-run modes, only `call_agent` is able to use the mock (or dry-run) model.
+    if output_mode == OutputMode::Json || mock::is_enabled() || dry_run::is_enabled() {
+        call_agent(ReasoningEffort::None, output_mode).await?;
+        return Ok(());
+    }
+
+    // With no reasoning, the model answers straight away.
+    // With a higher effort, the model "thinks" before answering - this is slower and uses more
+    // (output) tokens, but tends to give better answers for harder questions.
+    call_agent(ReasoningEffort::None, OutputMode::Human).await?;
+    call_agent(ReasoningEffort::High, OutputMode::Human).await?;
+    call_agent_raw().await?;
+    capped_vs_uncapped().await?;
+    call_agent_streaming().await?;
+    call_agent_with_image(
+        Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/orange-circle.png"
+        )),
+        "What's in this image?",
+    )
+    .await?;
+    extract_weather_report().await?;
+    circuit_breaker_example().await?;
+    chat_loop().await?;
+    Ok(())
+}
+
+/// How `call_agent` prints its result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputMode {
+    /// Readable output, including token usage and cost
+    Human,
+    /// A single JSON object of the form `{"prompt": ..., "response": ...}`
+    Json,
+}
+
+/// Sends a prompt to an agent, using the given amount of reasoning effort.
+/// Note that `ReasoningEffort::None` is only supported from GPT-5.1 onwards.
+/// In mock mode (`RIG_MOCK=1`), the reasoning effort is ignored and a fixed response is returned.
+/// In dry-run mode (`RIG_DRY_RUN=1`), the request is printed instead of being sent.
+async fn call_agent(
+    reasoning_effort: ReasoningEffort,
+    output_mode: OutputMode,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let prompt = "What is the Rust programming language?";
+    if output_mode == OutputMode::Human {
+        println!("{prompt} (reasoning effort: {reasoning_effort:?})");
+    }
+
+    let additional_params = AdditionalParameters {
+        reasoning: Some(Reasoning {
+            effort: Some(reasoning_effort),
+            summary: None,
+        }),
+        ..Default::default()
+    }
+    .to_json();
+
+    let response = if dry_run::is_enabled() {
+        // The agent's request is printed rather than sent.
+        // It's built in the same way as the real agent below, so the request is the same too.
+        let agent = AgentBuilder::new(DryRunCompletionModel::new(CHAT_MODEL))
+            .preamble("You are a helpful assistant.")
+            .name("Bob") // used in logging
+            .additional_params(additional_params)
+            .with_max_tokens(max_output_tokens())
+            .build();
+
+        call_agent_with_retry(&agent, prompt, 3).await?
+    } else if mock::is_enabled() {
+        let agent = AgentBuilder::new(MockCompletionModel::new(
+            "Rust is a systems programming language focused on safety, speed and concurrency.",
+        ))
+        .preamble("You are a helpful assistant.")
+        .name("Bob") // used in logging
+        .build();
+
+        call_agent_with_retry(&agent, prompt, 3).await?
+    } else {
+        let openai_client = Client::from_env();
+
+        let agent = openai_client
+            .agent(CHAT_MODEL)
+            .preamble("You are a helpful assistant.")
+            .name("Bob") // used in logging
+            .additional_params(additional_params)
+            .with_max_tokens(max_output_tokens())
+            .build();
+
+        call_agent_with_retry(&agent, prompt, 3).await?
+    };
+
+    if output_mode == OutputMode::Json {
+        let output = serde_json::json!({
+            "prompt": prompt,
+            "response": response.output,
+        });
+        println!("{output}");
+        return Ok(());
+    }
+
+    println!("Response: {}", response.output);
+
+    println!(
+        "Output tokens (including any reasoning): {}",
+        response.total_usage.output_tokens
+    );
+
+    let cost = estimate_cost(
+        "openai",
+        CHAT_MODEL,
+        response.total_usage.input_tokens,
+        response.total_usage.output_tokens,
+    );
+    println!("Estimated cost: ${cost:.6}");
+
+    Ok(())
+}
+
+/// Sends the same prompt as `call_agent`, but through the completion model directly rather than an agent.
+/// This gives us the full `CompletionResponse`, including the provider's raw response, rather than just the text.
+async fn call_agent_raw() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = Client::from_env();
+    let model = openai_client.completion_model(CHAT_MODEL);
+
+    let prompt = "What is the Rust programming language?";
+    println!("{prompt} (raw completion response)");
+
+    let response = model
+        .completion_request(prompt)
+        .preamble("You are a helpful assistant.".to_string())
+        .send()
+        .await?;
+
+    for content in response.choice.iter() {
+        if let AssistantContent::Text(Text { text }) = content {
+            println!("Response: {text}");
+        }
+    }
+
+    // The finish reason is provider-specific, so it comes from the raw response.
+    // With OpenAI's Responses API, an incomplete response (eg, from running out of tokens) says why.
+    println!("Status: {:?}", response.raw_response.status);
+    if let Some(details) = &response.raw_response.incomplete_details {
+        println!("Incomplete because: {}", details.reason);
+    }
+
+    println!(
+        "Token usage: {} input, {} output, {} total",
+        response.usage.input_tokens, response.usage.output_tokens, response.usage.total_tokens
+    );
+
+    // No tools were given here, so we don't expect the model to ask for one
+    let wants_tool_call = response
+        .choice
+        .iter()
+        .any(|content| matches!(content, AssistantContent::ToolCall(_)));
+    println!("Model wanted to call a tool: {wants_tool_call}");
+
     Ok(())
 }
 
-async fn call_agent() -> Result<(), Box<dyn std::error::Error>> {
+/// Sends a prompt that invites a long answer, first with a cap of 50 output tokens and then without one.
+/// The capped response is cut off part way through.
+async fn capped_vs_uncapped() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = Client::from_env();
+
+    // Reasoning tokens count towards the cap, so turn reasoning off to leave room for the answer
+    let additional_params = AdditionalParameters {
+        reasoning: Some(Reasoning {
+            effort: Some(ReasoningEffort::None),
+            summary: None,
+        }),
+        ..Default::default()
+    }
+    .to_json();
+
+    let prompt = "Explain the history of the Rust programming language in detail.";
+    println!("{prompt}");
+
+    for max_tokens in [Some(50), None] {
+        let agent = openai_client
+            .agent(CHAT_MODEL)
+            .preamble("You are a helpful assistant.")
+            .additional_params(additional_params.clone())
+            .with_max_tokens(max_tokens)
+            .build();
+
+        let response = agent.prompt(prompt).extended_details().await?;
+
+        println!(
+            "Max tokens: {max_tokens:?}, output tokens: {}, response length: {} characters",
+            response.total_usage.output_tokens,
+            response.output.len()
+        );
+        println!("Response: {}", response.output);
+    }
+
+    Ok(())
+}
+
+/// Prompts an agent, retrying transient failures with exponential backoff (plus some jitter).
+/// Fatal errors (like an invalid API key) are returned immediately, as retrying them won't help.
+/// The token usage is returned alongside the response, so the caller can estimate the cost.
+/// Progress is printed to stderr, so it doesn't get mixed in with the response on stdout.
+async fn call_agent_with_retry<M: CompletionModel>(
+    agent: &Agent<M>,
+    prompt: &str,
+    max_retries: u32,
+) -> Result<PromptResponse, PromptError> {
+    let mut attempt = 0;
+
+    retry(max_retries, is_retryable, || {
+        attempt += 1;
+        eprintln!("Attempt {attempt}/{}", max_retries + 1);
+
+        agent.prompt(prompt).extended_details().into_future()
+    })
+    .await
+}
+
+/// Whether an error is likely to be transient (and therefore worth retrying).
+fn is_retryable(error: &PromptError) -> bool {
+    match error {
+        // Connection errors, timeouts, etc.
+        PromptError::CompletionError(CompletionError::HttpError(_)) => true,
+        PromptError::CompletionError(CompletionError::ProviderError(msg)) => {
+            is_transient_message(msg)
+        }
+        _ => false,
+    }
+}
+
+/// The same as `call_agent`, but each text delta is printed as soon as it arrives
+/// rather than waiting for the whole response.
+async fn call_agent_streaming() -> Result<(), Box<dyn std::error::Error>> {
     let openai_client = Client::from_env();
 
     let agent = openai_client
-        .agent("gpt-5")
+        .agent(AGENT_MODEL)
         .preamble("You are a helpful assistant.")
         .name("Bob") // used in logging
+        .with_max_tokens(max_output_tokens())
         .build();
 
     let prompt = "What is the Rust programming language?";
     println!("{prompt}");
 
-    let response_text = agent.prompt(prompt).await?;
+    let mut stream = agent.stream_prompt(prompt).await;
+
+    print!("Response: ");
+    while let Some(item) = stream.next().await {
+        if let MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(Text {
+            text,
+        })) = item?
+        {
+            print!("{text}");
+            // stdout is line-buffered, so flush to make sure each delta shows up immediately
+            std::io::stdout().flush()?;
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Sends an image from a local file along with a text prompt, in a single user message.
+/// The image is base64-encoded, as the Responses API doesn't accept raw image bytes.
+async fn call_agent_with_image(
+    path: &Path,
+    prompt: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let media_type = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => ImageMediaType::PNG,
+        Some("jpg" | "jpeg") => ImageMediaType::JPEG,
+        Some("gif") => ImageMediaType::GIF,
+        Some("webp") => ImageMediaType::WEBP,
+        _ => return Err(format!("Unsupported image type: {}", path.display()).into()),
+    };
+    let data = BASE64_STANDARD.encode(std::fs::read(path)?);
+
+    let message = Message::User {
+        content: OneOrMany::many(vec![
+            UserContent::text(prompt),
+            UserContent::image_base64(data, Some(media_type), Some(ImageDetail::Auto)),
+        ])?,
+    };
+
+    let openai_client = Client::from_env();
+
+    // Not every model accepts images, so make sure to use one with vision support
+    let agent = openai_client
+        .agent(AGENT_MODEL)
+        .preamble("You are a helpful assistant.")
+        .name("Bob") // used in logging
+        .with_max_tokens(max_output_tokens())
+        .build();
+
+    println!("{prompt} (image: {})", path.display());
+
+    let response = agent.prompt(message).await?;
+    println!("Response: {response}");
+
+    Ok(())
+}
+
+/// A weather report extracted from free text.
+/// The JSON schema generated from this struct is sent to the model so it knows what to return.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct WeatherReport {
+    /// The city the weather report is for
+    city: String,
+    /// The temperature in degrees Celsius
+    temperature_celsius: f64,
+    /// A short description of the conditions, e.g. "sunny" or "light rain"
+    conditions: String,
+    /// Relative humidity as a percentage, if mentioned
+    humidity_percent: Option<u8>,
+}
+
+/// Uses an extractor to get a typed `WeatherReport` back from the model, rather than a `String`.
+async fn extract_weather_report() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = Client::from_env();
+
+    let extractor = openai_client
+        .extractor::<WeatherReport>(AGENT_MODEL)
+        .build();
+
+    let prompt = "Weather in Paris: currently 18 degrees and drizzling, with 80% humidity.";
+    println!("{prompt}");
+
+    let report = extractor.extract(prompt).await?;
+
+    println!("City: {}", report.city);
+    println!("Temperature: {}°C", report.temperature_celsius);
+    println!("Conditions: {}", report.conditions);
+    match report.humidity_percent {
+        Some(humidity) => println!("Humidity: {humidity}%"),
+        None => println!("Humidity: unknown"),
+    }
+
+    Ok(())
+}
+
+/// Sends calls through a circuit breaker while the "provider" is down, then again once it has recovered.
+/// The outage is simulated, but once the breaker has closed again, a real prompt is sent through it.
+///
+/// After 3 failures in a row, the breaker opens and the next call fails straight away without being made.
+/// Once the cooldown has passed, the breaker is half-open and lets a trial call through - which succeeds,
+/// closing the breaker again.
+async fn circuit_breaker_example() -> Result<(), Box<dyn std::error::Error>> {
+    let breaker = CircuitBreaker::new(3, Duration::from_secs(2), Duration::from_secs(30));
+
+    let provider_is_down = AtomicBool::new(true);
+    let simulated_call = || async {
+        if provider_is_down.load(Ordering::Relaxed) {
+            Err(PromptError::CompletionError(
+                CompletionError::ProviderError("503 Service Unavailable (simulated)".to_string()),
+            ))
+        } else {
+            Ok("Rust is a systems programming language.".to_string())
+        }
+    };
+
+    for attempt in 1..=4 {
+        match breaker.call(simulated_call).await {
+            Ok(response) => println!("Call {attempt} succeeded: {response}"),
+            Err(CircuitBreakerError::Open(remaining)) => {
+                println!("Call {attempt} failed fast, breaker is open for another {remaining:?}")
+            }
+            Err(e) => println!("Call {attempt} failed: {e}"),
+        }
+        println!("Breaker state: {:?}", breaker.state());
+    }
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    println!("Breaker state after cooldown: {:?}", breaker.state());
+
+    provider_is_down.store(false, Ordering::Relaxed);
+    let response = breaker.call(simulated_call).await?;
+    println!("Trial call succeeded: {response}");
+    println!("Breaker state: {:?}", breaker.state());
+
+    // Real provider calls are wrapped in exactly the same way
+    let agent = Client::from_env()
+        .agent(SMALL_CHAT_MODEL)
+        .preamble("You are a helpful assistant. Answer in one sentence.")
+        .with_max_tokens(max_output_tokens())
+        .build();
+    let response = breaker
+        .call(|| {
+            agent
+                .prompt("What is the Rust programming language?")
+                .into_future()
+        })
+        .await?;
+    println!("Real call through the breaker succeeded: {response}");
+
+    Ok(())
+}
+
+/// An interactive chat that reads prompts from stdin until the user types "exit".
+/// The conversation history is kept between turns, so later prompts can refer to earlier ones
+/// (for example, "What is Rust?" followed by "Who created it?").
+async fn chat_loop() -> Result<(), Box<dyn std::error::Error>> {
+    let openai_client = Client::from_env();
+
+    let agent = openai_client
+        .agent(AGENT_MODEL)
+        .preamble("You are a helpful assistant.")
+        .name("Bob") // used in logging
+        .with_max_tokens(max_output_tokens())
+        .build();
+
+    let mut history: Vec<Message> = Vec::new();
+
+    println!("Chat started. Type \"exit\" to quit.");
+
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        // A read of 0 bytes means stdin was closed
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+
+        let line = line.trim();
+        if line == "exit" {
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
 
-    println!("Response: {response_text}");
+        // `with_history` appends both the prompt and the response to the history for us
+        let response = agent.prompt(line).with_history(&mut history).await?;
+        println!("Response: {response}");
+    }
 
     Ok(())
 }
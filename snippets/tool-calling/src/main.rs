@@ -1,4 +1,5 @@
 //! This example showcases using a simple addition tool with an agent in Rig.
+//! A second agent is then given a unit conversion tool to answer a cooking question.
 use rig::prelude::*;
 use rig::{
     completion::{Prompt, ToolDefinition},
@@ -54,6 +55,90 @@ impl Tool for Adder {
     }
 }
 
+#[derive(Deserialize)]
+struct ConversionArgs {
+    value: f64,
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Cannot convert from {from} to {to}")]
+struct ConversionError {
+    from: String,
+    to: String,
+}
+
+/// Converts between units of mass.
+#[derive(Deserialize, Serialize)]
+struct ConvertUnits;
+
+impl ConvertUnits {
+    /// How many grams are in one of the given unit.
+    fn grams_per_unit(unit: &str) -> Option<f64> {
+        match unit {
+            "g" => Some(1.0),
+            "kg" => Some(1000.0),
+            "oz" => Some(28.349523125),
+            "lb" => Some(453.59237),
+            _ => None,
+        }
+    }
+}
+
+impl Tool for ConvertUnits {
+    const NAME: &'static str = "convert_units";
+    type Error = ConversionError;
+    type Args = ConversionArgs;
+    type Output = f64;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: "convert_units".to_string(),
+            description: "Convert a mass from one unit to another".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "value": {
+                        "type": "number",
+                        "description": "The value to convert"
+                    },
+                    "from": {
+                        "type": "string",
+                        "enum": ["g", "kg", "oz", "lb"],
+                        "description": "The unit to convert from"
+                    },
+                    "to": {
+                        "type": "string",
+                        "enum": ["g", "kg", "oz", "lb"],
+                        "description": "The unit to convert to"
+                    }
+                },
+                "required": ["value", "from", "to"],
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let (Some(from), Some(to)) = (
+            Self::grams_per_unit(&args.from),
+            Self::grams_per_unit(&args.to),
+        ) else {
+            return Err(ConversionError {
+                from: args.from,
+                to: args.to,
+            });
+        };
+
+        let result = args.value * from / to;
+        println!(
+            "[tool-call] Converting {} {} to {}: {result}",
+            args.value, args.from, args.to
+        );
+        Ok(result)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let openai_client = providers::openai::Client::from_env();
@@ -71,5 +156,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let response = calculator_agent.prompt(prompt).await?;
     println!("OpenAI Calculator Agent: {response}");
 
+    let cooking_agent = openai_client
+        .agent(providers::openai::GPT_5)
+        .preamble("You are a cooking assistant. Use the tools provided to convert between units rather than estimating.")
+        .max_tokens(1024)
+        .tool(ConvertUnits)
+        .build();
+
+    let prompt = "How much does 4oz of parmesan cheese weigh in grams?";
+    println!("Prompt: {prompt}");
+
+    // The result of the tool call is sent back to the model, which uses it in the final answer
+    let response = cooking_agent.prompt(prompt).await?;
+    println!("OpenAI Cooking Agent: {response}");
+
     Ok(())
 }